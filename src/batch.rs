@@ -0,0 +1,228 @@
+use super::*;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+/// Batch of coalesced deltas
+///
+/// Holds at most one delta per key, the join of every delta pushed for that key, so a stream of
+/// changes to the same members or keys of a [Set] or [Map] is sent as a single [Register] each.
+/// Merging the batch has the same effect as merging every delta pushed into it.
+#[derive(Clone, Debug)]
+pub struct DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    deltas: HashMap<C::DeltaKey, C::Delta>,
+}
+
+impl<C> Default for DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    fn default() -> Self {
+        DeltaBatch::new()
+    }
+}
+
+impl<C> PartialEq for DeltaBatch<C>
+where
+    C: Coalesce,
+    C::Delta: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.deltas == other.deltas
+    }
+}
+
+impl<C> Eq for DeltaBatch<C>
+where
+    C: Coalesce,
+    C::Delta: Eq,
+{
+}
+
+impl<C> DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    /// Create a new empty `DeltaBatch`
+    pub fn new() -> DeltaBatch<C> {
+        DeltaBatch {
+            deltas: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of keys in the batch.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Returns true if the batch has no deltas.
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Add a delta, coalescing it with any delta for the same key.
+    pub fn push(&mut self, delta: C::Delta) {
+        let key = C::delta_key(&delta);
+        match self.deltas.get_mut(&key) {
+            Some(batched) => C::coalesce(batched, &delta),
+            None => {
+                self.deltas.insert(key, delta);
+            }
+        }
+    }
+
+    /// Add every delta of another batch.
+    pub fn merge(&mut self, other: &Self)
+    where
+        C::Delta: Clone,
+    {
+        for delta in other.iter() {
+            self.push(delta.clone());
+        }
+    }
+
+    /// An iterator visiting the coalesced deltas in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = &C::Delta> + '_ {
+        self.deltas.values()
+    }
+
+    /// Merge every delta into a replica.
+    pub fn apply(self, crdt: &mut C) {
+        for delta in self {
+            crdt.apply_delta(delta);
+        }
+    }
+}
+
+impl<C> Extend<C::Delta> for DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    fn extend<I: IntoIterator<Item = C::Delta>>(&mut self, iter: I) {
+        for delta in iter {
+            self.push(delta);
+        }
+    }
+}
+
+impl<C> FromIterator<C::Delta> for DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    fn from_iter<I: IntoIterator<Item = C::Delta>>(iter: I) -> Self {
+        let mut batch = DeltaBatch::new();
+        batch.extend(iter);
+        batch
+    }
+}
+
+impl<C> IntoIterator for DeltaBatch<C>
+where
+    C: Coalesce,
+{
+    type Item = C::Delta;
+    type IntoIter = std::collections::hash_map::IntoValues<C::DeltaKey, C::Delta>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.deltas.into_values()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl<C> Serialize for DeltaBatch<C>
+    where
+        C: Coalesce,
+        C::Delta: Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    impl<'de, C> Deserialize<'de> for DeltaBatch<C>
+    where
+        C: Coalesce,
+        C::Delta: Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let deltas = Vec::<C::Delta>::deserialize(deserializer)?;
+            Ok(deltas.into_iter().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    type Sizes = Map<u8, u8, u8, u8>;
+
+    #[test]
+    fn test_coalesce_map() {
+        let mut map: Map<&str, u32, u32, u16> = Map::new();
+        let mut batch = DeltaBatch::new();
+        for i in 0..10 {
+            batch.push(map.insert("foo", i, i));
+        }
+        batch.push(map.insert("bar", 1, 1));
+        batch.extend(map.remove("bar", 2));
+        assert_eq!(batch.len(), 2);
+
+        let mut replica = Map::new();
+        batch.apply(&mut replica);
+        assert_eq!(replica, map);
+    }
+
+    #[test]
+    fn test_coalesce_set() {
+        let mut set: Set<u8, u32, u16> = Set::new();
+        let mut batch: DeltaBatch<_> = (0..4).map(|i| set.add(i % 2, i.into())).collect();
+        batch.extend(set.remove(0, 5));
+        assert_eq!(batch.len(), 2);
+
+        let mut other = DeltaBatch::new();
+        other.push(set.add(0, 6));
+        batch.merge(&other);
+        let mut replica = Set::new();
+        batch.apply(&mut replica);
+        assert_eq!(replica, set);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut set: Set<u8, u32, u16> = Set::new();
+        let batch: DeltaBatch<_> = vec![set.add(1, 1), set.remove(1, 2).unwrap()]
+            .into_iter()
+            .collect();
+
+        let data = serde_json::to_string(&batch).unwrap();
+        let batch2: DeltaBatch<Set<u8, u32, u16>> = serde_json::from_str(&data).unwrap();
+        assert_eq!(batch, batch2);
+    }
+
+    // merging the batch is the same as merging each delta
+    #[quickcheck]
+    fn is_batch_equivalent(xs: Vec<Register<(u8, u8), u8, u8>>) -> bool {
+        let mut left = Sizes::new();
+        for delta in xs.iter().cloned() {
+            left.merge_register(delta, 0);
+        }
+        let mut right = Sizes::new();
+        xs.into_iter().collect::<DeltaBatch<_>>().apply(&mut right);
+        left == right
+    }
+}
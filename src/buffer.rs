@@ -0,0 +1,309 @@
+use super::*;
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Message sent by a [DeltaBuffer] to one peer
+///
+/// Once applied, the peer acknowledges the sequence number back to the sender with
+/// [DeltaBuffer::ack].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum DeltaMessage<C, D> {
+    /// Every delta the peer hasn't acknowledged, up to sequence number `seq`
+    Deltas { seq: u64, deltas: Vec<D> },
+    /// Full state, for a peer which fell behind the buffered deltas
+    State { seq: u64, state: C },
+}
+
+impl<C, D> DeltaMessage<C, D>
+where
+    C: DeltaCrdt<Delta = D>,
+{
+    /// Returns the sequence number to acknowledge once the message is applied.
+    pub fn seq(&self) -> u64 {
+        match self {
+            DeltaMessage::Deltas { seq, .. } | DeltaMessage::State { seq, .. } => *seq,
+        }
+    }
+
+    /// Apply the message to a replica.
+    ///
+    /// Returns the sequence number to acknowledge.
+    pub fn apply(self, crdt: &mut C) -> u64 {
+        match self {
+            DeltaMessage::Deltas { seq, deltas } => {
+                for delta in deltas {
+                    crdt.apply_delta(delta);
+                }
+                seq
+            }
+            DeltaMessage::State { seq, state } => {
+                crdt.join(&state);
+                seq
+            }
+        }
+    }
+}
+
+/// Outgoing delta buffer
+///
+/// Implements the delta-interval protocol from ["Delta State Replicated Data
+/// Types"](https://arxiv.org/abs/1603.01529): deltas are buffered with increasing sequence numbers,
+/// each peer is sent every delta after the last sequence number it acknowledged, and deltas are
+/// dropped once all peers have acknowledged them.
+///
+/// Repeated deltas for the same key are coalesced, so the buffer holds at most one delta per key.
+/// A peer which falls behind the oldest buffered delta, either because it was added later or
+/// because the buffer overflowed its capacity, is sent the full state instead. Every change to the
+/// replica after the buffer is created should be pushed, so the buffered deltas are never missing
+/// anything a peer needs.
+#[derive(Clone, Debug)]
+pub struct DeltaBuffer<C, P>
+where
+    C: Coalesce,
+    P: Key,
+{
+    seq: u64,
+    // sequence number of the newest delta dropped from the buffer
+    floor: u64,
+    capacity: usize,
+    deltas: HashMap<C::DeltaKey, (u64, C::Delta)>,
+    // the key of each buffered delta, by the sequence number it was last updated at
+    order: BTreeMap<u64, C::DeltaKey>,
+    acked: HashMap<P, u64>,
+}
+
+impl<C, P> Default for DeltaBuffer<C, P>
+where
+    C: Coalesce,
+    P: Key,
+{
+    fn default() -> Self {
+        DeltaBuffer::new()
+    }
+}
+
+impl<C, P> DeltaBuffer<C, P>
+where
+    C: Coalesce,
+    P: Key,
+{
+    /// Create a new empty `DeltaBuffer`, with no limit on the number of buffered deltas
+    pub fn new() -> DeltaBuffer<C, P> {
+        DeltaBuffer::with_capacity(usize::MAX)
+    }
+
+    /// Create a new empty `DeltaBuffer`, which holds at most `capacity` deltas
+    ///
+    /// When more keys are changed, the oldest deltas are dropped, and peers which haven't
+    /// acknowledged them are sent the full state.
+    pub fn with_capacity(capacity: usize) -> DeltaBuffer<C, P> {
+        DeltaBuffer {
+            seq: 0,
+            floor: 0,
+            capacity,
+            deltas: HashMap::new(),
+            order: BTreeMap::new(),
+            acked: HashMap::new(),
+        }
+    }
+
+    /// Returns the sequence number of the newest delta.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the number of buffered deltas.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Returns true if no deltas are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Start sending deltas to a peer, which has acknowledged nothing yet.
+    ///
+    /// Does nothing if the peer is already known.
+    pub fn add_peer(&mut self, peer: P) {
+        self.acked.entry(peer).or_insert(0);
+    }
+
+    /// Stop sending deltas to a peer, dropping any deltas only it was waiting for.
+    pub fn remove_peer(&mut self, peer: &P) {
+        self.acked.remove(peer);
+        self.collect();
+    }
+
+    /// Buffer a delta, to be sent to every peer.
+    ///
+    /// Returns the delta's sequence number.
+    pub fn push(&mut self, delta: C::Delta) -> u64 {
+        self.seq += 1;
+        let key = C::delta_key(&delta);
+        match self.deltas.get_mut(&key) {
+            Some((seq, buffered)) => {
+                C::coalesce(buffered, &delta);
+                self.order.remove(seq);
+                *seq = self.seq;
+            }
+            None => {
+                self.deltas.insert(key.clone(), (self.seq, delta));
+            }
+        }
+        self.order.insert(self.seq, key);
+
+        while self.deltas.len() > self.capacity {
+            if let Some((seq, key)) = self.order.pop_first() {
+                self.deltas.remove(&key);
+                self.floor = seq;
+            }
+        }
+        self.seq
+    }
+
+    /// Returns the message to send to a peer, or `None` if the peer is up to date or unknown.
+    ///
+    /// `state` is the replica the deltas were produced by, which is sent in full if the peer
+    /// fell behind the buffered deltas.
+    pub fn message(&self, peer: &P, state: &C) -> Option<DeltaMessage<C, C::Delta>>
+    where
+        C: Clone,
+        C::Delta: Clone,
+    {
+        let acked = *self.acked.get(peer)?;
+        if acked >= self.seq {
+            None
+        } else if acked < self.floor {
+            Some(DeltaMessage::State {
+                seq: self.seq,
+                state: state.clone(),
+            })
+        } else {
+            let deltas = self
+                .order
+                .range(acked + 1..)
+                .map(|(_, key)| self.deltas[key].1.clone())
+                .collect();
+            Some(DeltaMessage::Deltas {
+                seq: self.seq,
+                deltas,
+            })
+        }
+    }
+
+    /// Record that a peer has applied everything up to sequence number `seq`, and drop the deltas
+    /// every peer has acknowledged.
+    pub fn ack(&mut self, peer: &P, seq: u64) {
+        if let Some(acked) = self.acked.get_mut(peer) {
+            *acked = max(*acked, seq);
+            self.collect();
+        }
+    }
+
+    fn collect(&mut self) {
+        let horizon = self.acked.values().copied().min().unwrap_or(self.seq);
+        while let Some(entry) = self.order.first_entry() {
+            if *entry.key() > horizon {
+                break;
+            }
+            let (seq, key) = entry.remove_entry();
+            self.deltas.remove(&key);
+            self.floor = max(self.floor, seq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Tags = Set<&'static str, u32, u16>;
+
+    #[test]
+    fn test_deltas_and_acks() {
+        let mut set: Tags = Set::new();
+        let mut buffer: DeltaBuffer<Tags, u8> = DeltaBuffer::new();
+        buffer.add_peer(1);
+        buffer.add_peer(2);
+
+        buffer.push(set.add("foo", 1));
+        buffer.push(set.add("bar", 2));
+        // coalesced with the add of the same member
+        buffer.push(set.remove("foo", 3).unwrap());
+        assert_eq!(buffer.len(), 2);
+
+        let mut peer1: Tags = Set::new();
+        let message = buffer.message(&1, &set).unwrap();
+        assert!(matches!(&message, DeltaMessage::Deltas { deltas, .. } if deltas.len() == 2));
+        let seq = message.apply(&mut peer1);
+        assert_eq!(peer1, set);
+        buffer.ack(&1, seq);
+        assert_eq!(buffer.message(&1, &set), None);
+
+        // still buffered for the other peer
+        assert_eq!(buffer.len(), 2);
+        buffer.push(set.add("baz", 4));
+        let mut peer2: Tags = Set::new();
+        let seq = buffer.message(&2, &set).unwrap().apply(&mut peer2);
+        buffer.ack(&2, seq);
+        assert_eq!(peer2, set);
+
+        // only the newest delta is still waiting for the first peer
+        assert_eq!(buffer.len(), 1);
+        let message = buffer.message(&1, &set).unwrap();
+        assert!(matches!(&message, DeltaMessage::Deltas { deltas, .. } if deltas.len() == 1));
+    }
+
+    #[test]
+    fn test_full_state_fallback() {
+        let mut map: Map<u8, u32, u32, u16> = Map::new();
+        let mut buffer: DeltaBuffer<_, u8> = DeltaBuffer::with_capacity(2);
+        buffer.add_peer(1);
+        for i in 0..4 {
+            buffer.push(map.insert(i, 10, 1));
+        }
+        assert_eq!(buffer.len(), 2);
+
+        // the peer missed dropped deltas
+        let message = buffer.message(&1, &map).unwrap();
+        assert!(matches!(message, DeltaMessage::State { seq: 4, .. }));
+        let mut peer1 = Map::new();
+        buffer.ack(&1, message.apply(&mut peer1));
+        assert_eq!(peer1, map);
+        assert!(buffer.is_empty());
+
+        // a peer added after deltas were dropped starts from the full state
+        buffer.add_peer(2);
+        assert!(matches!(
+            buffer.message(&2, &map),
+            Some(DeltaMessage::State { .. })
+        ));
+        buffer.push(map.insert(0, 20, 2));
+        assert!(matches!(
+            buffer.message(&1, &map),
+            Some(DeltaMessage::Deltas { .. })
+        ));
+    }
+
+    #[test]
+    fn test_lost_message() {
+        let mut reg: Register<u8, u32, u16> = Register::new(1, 1);
+        let mut buffer: DeltaBuffer<_, u8> = DeltaBuffer::new();
+        buffer.add_peer(1);
+        buffer.push(reg.set(2, 2));
+        let _lost = buffer.message(&1, &reg);
+        buffer.push(reg.set(3, 3));
+        assert_eq!(buffer.len(), 1);
+
+        // everything unacknowledged is sent again
+        let mut peer1 = Register::bottom();
+        let seq = buffer.message(&1, &reg).unwrap().apply(&mut peer1);
+        assert_eq!(seq, 2);
+        assert_eq!(peer1, reg);
+    }
+}
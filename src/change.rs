@@ -0,0 +1,38 @@
+/// Visible effect of merging a delta into a collection.
+///
+/// [Set](crate::Set) reports changes without a value, [Map](crate::Map) reports the values that
+/// became visible or hidden.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change<K, V = ()> {
+    /// The key became visible with the given value.
+    Added(K, V),
+    /// The key was hidden. Carries the value that was visible before.
+    Removed(K, V),
+    /// The key stayed visible, but the value changed.
+    Updated { key: K, old: V, new: V },
+    /// The delta was merged, but made no visible difference.
+    Unchanged(K),
+    /// The delta was an excessively old remove, and was ignored.
+    Ignored(K),
+}
+
+impl<K, V> Change<K, V> {
+    /// Accessor for the key affected by the change
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Added(key, _)
+            | Change::Removed(key, _)
+            | Change::Updated { key, .. }
+            | Change::Unchanged(key)
+            | Change::Ignored(key) => key,
+        }
+    }
+
+    /// Returns true if the change altered the visible state of the collection.
+    pub fn is_visible(&self) -> bool {
+        matches!(
+            self,
+            Change::Added(..) | Change::Removed(..) | Change::Updated { .. }
+        )
+    }
+}
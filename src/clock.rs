@@ -0,0 +1,265 @@
+use super::*;
+use std::cmp::max;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// A clock which stamps local operations with tags.
+///
+/// The clock is its own tag type: its current value is the last tag it produced or observed.
+pub trait Clock: TagT {
+    /// Advance the clock for a local operation, returning the new tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the clock has run out of tags, which a tag observed from a faulty replica can
+    /// cause. A tag at or below an earlier one would be silently ignored by merges.
+    fn tick(&mut self) -> Self;
+
+    /// Advance the clock past a tag received from another replica.
+    fn observe(&mut self, remote: Self);
+}
+
+const EXHAUSTED: &str = "clock ran out of tags";
+
+/// Lamport timestamp, with a replica id to break ties
+///
+/// Ordered by time, then replica id. Serializes as a `[time, replica]` pair.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct LamportClock<R = u32>(u64, R);
+
+impl<R> LamportClock<R>
+where
+    R: Copy + Ord + Default,
+{
+    /// Create a new `LamportClock` for a replica, at time zero
+    pub fn new(replica: R) -> LamportClock<R> {
+        LamportClock(0, replica)
+    }
+
+    /// Accessor for the logical time
+    pub fn time(&self) -> u64 {
+        self.0
+    }
+
+    /// Accessor for the replica id
+    pub fn replica(&self) -> R {
+        self.1
+    }
+}
+
+impl<R> Clock for LamportClock<R>
+where
+    R: Copy + Ord + Default,
+{
+    fn tick(&mut self) -> Self {
+        self.0 = self.0.checked_add(1).expect(EXHAUSTED);
+        *self
+    }
+
+    fn observe(&mut self, remote: Self) {
+        self.0 = max(self.0, remote.0);
+    }
+}
+
+/// Hybrid logical clock, with a replica id to break ties
+///
+/// Follows physical time in milliseconds since the Unix epoch where it can, and a logical counter
+/// where physical time alone can't order events, as described in ["Logical Physical Clocks and
+/// Consistent Snapshots in Globally Distributed Databases"][hlc]. Should the counter run out, the
+/// clock carries into the physical part, running a millisecond ahead.
+///
+/// [hlc]: http://www.cse.buffalo.edu/tech-reports/2014-04.pdf
+///
+/// Ordered by physical time, then logical counter, then replica id. Serializes as a
+/// `[millis, counter, replica]` triple.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct HybridLogicalClock<R = u32>(u64, u32, R);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl<R> HybridLogicalClock<R>
+where
+    R: Copy + Ord + Default,
+{
+    /// Create a new `HybridLogicalClock` for a replica, at the epoch
+    pub fn new(replica: R) -> HybridLogicalClock<R> {
+        HybridLogicalClock(0, 0, replica)
+    }
+
+    /// Accessor for the physical part, in milliseconds since the Unix epoch
+    pub fn millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Accessor for the logical counter
+    pub fn counter(&self) -> u32 {
+        self.1
+    }
+
+    /// Accessor for the replica id
+    pub fn replica(&self) -> R {
+        self.2
+    }
+
+    /// Advance the clock for a local operation at physical time `now`, returning the new tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the clock has run out of tags, see [Clock::tick].
+    pub fn tick_at(&mut self, now: u64) -> Self {
+        if now > self.0 {
+            self.0 = now;
+            self.1 = 0;
+        } else {
+            self.step(self.0, self.1);
+        }
+        *self
+    }
+
+    /// Advance the clock past a remote tag, received at physical time `now`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the clock has run out of tags, see [Clock::tick].
+    pub fn observe_at(&mut self, remote: Self, now: u64) {
+        let millis = max(max(self.0, remote.0), now);
+        if millis == self.0 && millis == remote.0 {
+            self.step(millis, max(self.1, remote.1));
+        } else if millis == self.0 {
+            self.step(millis, self.1);
+        } else if millis == remote.0 {
+            self.step(millis, remote.1);
+        } else {
+            self.0 = millis;
+            self.1 = 0;
+        }
+    }
+
+    // Move to the tag after `(millis, counter)`, carrying into the next millisecond once the
+    // counter runs out.
+    fn step(&mut self, millis: u64, counter: u32) {
+        match counter.checked_add(1) {
+            Some(counter) => {
+                self.0 = millis;
+                self.1 = counter;
+            }
+            None => {
+                self.0 = millis.checked_add(1).expect(EXHAUSTED);
+                self.1 = 0;
+            }
+        }
+    }
+}
+
+impl<R> Clock for HybridLogicalClock<R>
+where
+    R: Copy + Ord + Default,
+{
+    fn tick(&mut self) -> Self {
+        self.tick_at(now_millis())
+    }
+
+    fn observe(&mut self, remote: Self) {
+        self.observe_at(remote, now_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lamport() {
+        let mut a = LamportClock::new(1u32);
+        let mut b = LamportClock::new(2u32);
+        let t1 = a.tick();
+        let t2 = b.tick();
+        // same time, the replica id decides
+        assert!(t1 < t2);
+
+        b.observe(a.tick());
+        assert!(b.tick() > a);
+        assert_eq!(b.time(), 3);
+
+        let mut s: Set<&str, LamportClock, u16> = Set::new();
+        s.add("foo", a.tick());
+        assert_eq!(s.get("foo"), Some(a));
+    }
+
+    #[test]
+    fn test_hybrid_logical() {
+        let mut a = HybridLogicalClock::new(1u32);
+        let mut b = HybridLogicalClock::new(2u32);
+        let t1 = a.tick_at(100);
+        assert_eq!((t1.millis(), t1.counter()), (100, 0));
+        // physical time going backwards doesn't go back
+        let t2 = a.tick_at(90);
+        assert_eq!((t2.millis(), t2.counter()), (100, 1));
+
+        // b's physical clock is behind a's
+        b.observe_at(t2, 50);
+        let t3 = b.tick_at(60);
+        assert!(t3 > t2);
+        assert_eq!((t3.millis(), t3.counter()), (100, 3));
+
+        let t4 = b.tick();
+        assert!(t4 > t3);
+    }
+
+    #[test]
+    fn test_hybrid_logical_carry() {
+        let mut a = HybridLogicalClock::new(1u32);
+        a.tick_at(100);
+        a.1 = u32::MAX - 1;
+        let t1 = a.tick_at(100);
+        assert_eq!((t1.millis(), t1.counter()), (100, u32::MAX));
+        // the counter carries into the physical part
+        let t2 = a.tick_at(100);
+        assert_eq!((t2.millis(), t2.counter()), (101, 0));
+        assert!(t2 > t1);
+
+        let mut b = HybridLogicalClock::new(2u32);
+        b.observe_at(t1, 50);
+        assert!(b > t1);
+        assert_eq!((b.millis(), b.counter()), (101, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "clock ran out of tags")]
+    fn test_lamport_exhausted() {
+        let mut a = LamportClock::new(1u32);
+        a.observe(LamportClock(u64::MAX, 2));
+        a.tick();
+    }
+
+    #[test]
+    #[should_panic(expected = "clock ran out of tags")]
+    fn test_hybrid_logical_exhausted() {
+        let mut a = HybridLogicalClock::new(1u32);
+        a.observe_at(HybridLogicalClock(u64::MAX, u32::MAX, 2), 100);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut a = LamportClock::new(7u32);
+        a.tick();
+        assert_eq!(serde_json::to_string(&a).unwrap(), "[1,7]");
+
+        let mut h = HybridLogicalClock::new(7u32);
+        h.tick_at(1000);
+        let data = serde_json::to_string(&h).unwrap();
+        assert_eq!(data, "[1000,0,7]");
+        let h2: HybridLogicalClock = serde_json::from_str(&data).unwrap();
+        assert_eq!(h, h2);
+    }
+}
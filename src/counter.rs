@@ -0,0 +1,657 @@
+use super::*;
+use crate::register::Register;
+use num_traits::{CheckedAdd, CheckedSub, Signed};
+use std::cmp::max;
+use std::collections::HashMap;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Identifies one of the monotonic totals a counter is built from.
+///
+/// Counter deltas are [Register]s keyed by `CounterKey`, where the causal length holds the total.
+/// Totals only grow, so deltas merge by taking the maximum, out of order and redundantly, just
+/// like the causal lengths of a [Set].
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum CounterKey<R> {
+    /// Total incremented by a replica
+    Increment(R),
+    /// Total decremented by a replica
+    Decrement(R),
+    /// Total of rights transferred between two replicas of a [BoundedCounter]
+    Transfer(R, R),
+}
+
+/// Grow-only totals, merged by maximum
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Totals<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength,
+{
+    map: HashMap<CounterKey<R>, (Tag, N)>,
+}
+
+impl<R, Tag, N> Totals<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength,
+{
+    fn new() -> Totals<R, Tag, N> {
+        Totals {
+            map: HashMap::new(),
+        }
+    }
+
+    // `None` if the amount is negative or the total would overflow, leaving the total as is
+    fn add(
+        &mut self,
+        key: CounterKey<R>,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>>
+    where
+        N: CheckedAdd,
+    {
+        if amount < N::zero() {
+            return None;
+        }
+        let e = self.map.entry(key.clone()).or_insert((tag, N::zero()));
+        let total = e.1.checked_add(&amount)?;
+        e.0 = max(e.0, tag);
+        e.1 = total;
+        Some(Register::make(key, e.0, e.1))
+    }
+
+    // true if the total grew
+    fn merge_register(&mut self, delta: Register<CounterKey<R>, Tag, N>) -> bool {
+        let Register { item, tag, length } = delta;
+        let e = self.map.entry(item).or_insert((tag, N::zero()));
+        let grew = length > e.1;
+        e.0 = max(e.0, tag);
+        e.1 = max(e.1, length);
+        grew
+    }
+
+    fn register_iter(&self) -> impl Iterator<Item = Register<CounterKey<R>, Tag, N>> + '_ {
+        self.map
+            .iter()
+            .map(|(k, (tag, n))| Register::make(k.clone(), *tag, *n))
+    }
+
+    // The totals matching `up`, less the totals matching `down`, or `None` if that overflows.
+    //
+    // Adds while the running total isn't positive and subtracts while it is, so it stays between
+    // zero and the largest total until one side runs out, then moves straight to the result. It
+    // only overflows if the result does.
+    fn balance<U, D>(&self, up: U, down: D) -> Option<N>
+    where
+        U: Fn(&CounterKey<R>) -> bool,
+        D: Fn(&CounterKey<R>) -> bool,
+        N: CheckedAdd + CheckedSub,
+    {
+        let mut ups = self.map.iter().filter(|(k, _)| up(k)).map(|(_, (_, n))| *n);
+        let mut downs = self
+            .map
+            .iter()
+            .filter(|(k, _)| down(k))
+            .map(|(_, (_, n))| *n);
+        let mut acc = N::zero();
+        loop {
+            let next = if acc > N::zero() {
+                downs.next().map(|n| (n, false))
+            } else {
+                ups.next().map(|n| (n, true))
+            };
+            acc = match next {
+                Some((n, true)) => acc.checked_add(&n)?,
+                Some((n, false)) => acc.checked_sub(&n)?,
+                None => break,
+            };
+        }
+        let acc = ups.try_fold(acc, |acc, n| acc.checked_add(&n))?;
+        downs.try_fold(acc, |acc, n| acc.checked_sub(&n))
+    }
+
+    fn value(&self) -> Option<N>
+    where
+        N: CheckedAdd + CheckedSub,
+    {
+        self.balance(
+            |k| matches!(k, CounterKey::Increment(_)),
+            |k| matches!(k, CounterKey::Decrement(_)),
+        )
+    }
+}
+
+/// Causal Length Counter
+///
+/// A PN-counter: every replica keeps a grow-only total of its increments and of its decrements.
+/// Deltas are [Register]s carrying those totals, so they can be merged out of order and
+/// redundantly. The replica id is passed to each operation, the same way as the tag.
+///
+/// Negative amounts, and amounts which would overflow a replica's total, are rejected.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Counter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    totals: Totals<R, Tag, N>,
+}
+
+impl<R, Tag, N> Counter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    /// Create a new `Counter` with a value of zero
+    pub fn new() -> Counter<R, Tag, N> {
+        Counter {
+            totals: Totals::new(),
+        }
+    }
+
+    /// Returns the value of the counter, or `None` if it doesn't fit in `N`.
+    ///
+    /// Each replica's totals are bounded, but the value combines the totals of every replica.
+    pub fn value(&self) -> Option<N> {
+        self.totals.value()
+    }
+
+    /// Increment the counter on behalf of `replica`.
+    ///
+    /// Returns the delta [Register] to merge into other replicas, or `None` if `amount` is
+    /// negative or `replica`'s total would overflow.
+    pub fn increment(
+        &mut self,
+        replica: R,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>> {
+        self.totals.add(CounterKey::Increment(replica), amount, tag)
+    }
+
+    /// Decrement the counter on behalf of `replica`.
+    ///
+    /// Returns the delta [Register] to merge into other replicas, or `None` if `amount` is
+    /// negative or `replica`'s total would overflow.
+    pub fn decrement(
+        &mut self,
+        replica: R,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>> {
+        self.totals.add(CounterKey::Decrement(replica), amount, tag)
+    }
+
+    /// An iterator visiting all delta registers in arbitrary order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<CounterKey<R>, Tag, N>> + '_ {
+        self.totals.register_iter()
+    }
+
+    /// Merge a delta [Register] into the counter.
+    pub fn merge_register(&mut self, delta: Register<CounterKey<R>, Tag, N>) {
+        self.totals.merge_register(delta);
+    }
+
+    /// Merge two counters.
+    pub fn merge(&mut self, other: &Self) {
+        for delta in other.register_iter() {
+            self.merge_register(delta);
+        }
+    }
+}
+
+impl<R, Tag, N> DeltaCrdt for Counter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    type Delta = Register<CounterKey<R>, Tag, N>;
+
+    fn bottom() -> Self {
+        Counter::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.totals.merge_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+/// Causal Length Bounded Counter
+///
+/// A counter whose value never drops below zero, even under concurrent decrements. Each replica
+/// may only decrement by the rights it holds: its own increments, plus rights transferred to it
+/// by other replicas, less its decrements and the rights it transferred away.
+///
+/// Negative amounts, and amounts which would overflow a replica's total, are rejected.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BoundedCounter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    totals: Totals<R, Tag, N>,
+}
+
+impl<R, Tag, N> BoundedCounter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    /// Create a new `BoundedCounter` with a value of zero
+    pub fn new() -> BoundedCounter<R, Tag, N> {
+        BoundedCounter {
+            totals: Totals::new(),
+        }
+    }
+
+    /// Returns the value of the counter, or `None` if it doesn't fit in `N`.
+    ///
+    /// Each replica's totals are bounded, but the value combines the totals of every replica.
+    pub fn value(&self) -> Option<N> {
+        self.totals.value()
+    }
+
+    /// Returns the amount `replica` may decrement or transfer, or `None` if it doesn't fit in `N`.
+    ///
+    /// Rights transferred from several replicas can add up to more than `N` holds, in which case
+    /// `replica` can't decrement or transfer any of them.
+    pub fn rights(&self, replica: &R) -> Option<N> {
+        self.totals.balance(
+            |k| match k {
+                CounterKey::Increment(r) | CounterKey::Transfer(_, r) => r == replica,
+                CounterKey::Decrement(_) => false,
+            },
+            |k| match k {
+                CounterKey::Decrement(r) | CounterKey::Transfer(r, _) => r == replica,
+                CounterKey::Increment(_) => false,
+            },
+        )
+    }
+
+    // true if `replica` holds fewer than `amount` rights, or more than fit in `N`
+    fn lacks(&self, replica: &R, amount: N) -> bool {
+        !matches!(self.rights(replica), Some(rights) if rights >= amount)
+    }
+
+    /// Increment the counter on behalf of `replica`, which gains the same amount of rights.
+    ///
+    /// Returns the delta [Register] to merge into other replicas, or `None` if `amount` is
+    /// negative or `replica`'s total would overflow.
+    pub fn increment(
+        &mut self,
+        replica: R,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>> {
+        self.totals.add(CounterKey::Increment(replica), amount, tag)
+    }
+
+    /// Decrement the counter on behalf of `replica`.
+    ///
+    /// Returns the delta [Register] to merge into other replicas, or `None` if `amount` is
+    /// negative, `replica` doesn't hold enough rights, or its rights don't fit in `N`.
+    pub fn decrement(
+        &mut self,
+        replica: R,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>> {
+        if self.lacks(&replica, amount) {
+            return None;
+        }
+        self.totals.add(CounterKey::Decrement(replica), amount, tag)
+    }
+
+    /// Transfer rights from replica `from` to replica `to`.
+    ///
+    /// Returns the delta [Register] to merge into other replicas, or `None` if `amount` is
+    /// negative, `from` doesn't hold enough rights, or its rights don't fit in `N`.
+    pub fn transfer(
+        &mut self,
+        from: R,
+        to: R,
+        amount: N,
+        tag: Tag,
+    ) -> Option<Register<CounterKey<R>, Tag, N>> {
+        if self.lacks(&from, amount) {
+            return None;
+        }
+        self.totals.add(CounterKey::Transfer(from, to), amount, tag)
+    }
+
+    /// An iterator visiting all delta registers in arbitrary order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<CounterKey<R>, Tag, N>> + '_ {
+        self.totals.register_iter()
+    }
+
+    /// Merge a delta [Register] into the counter.
+    pub fn merge_register(&mut self, delta: Register<CounterKey<R>, Tag, N>) {
+        self.totals.merge_register(delta);
+    }
+
+    /// Merge two counters.
+    pub fn merge(&mut self, other: &Self) {
+        for delta in other.register_iter() {
+            self.merge_register(delta);
+        }
+    }
+}
+
+impl<R, Tag, N> DeltaCrdt for BoundedCounter<R, Tag, N>
+where
+    R: Key,
+    Tag: TagT,
+    N: CausalLength + Signed + CheckedAdd + CheckedSub,
+{
+    type Delta = Register<CounterKey<R>, Tag, N>;
+
+    fn bottom() -> Self {
+        BoundedCounter::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.totals.merge_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::de::{SeqAccess, Visitor};
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt::Formatter;
+    use std::marker::PhantomData;
+
+    impl<R, Tag, N> Serialize for Totals<R, Tag, N>
+    where
+        R: Key + Serialize,
+        Tag: TagT + Serialize,
+        N: CausalLength + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq = serializer.serialize_seq(Some(self.map.len()))?;
+            for (key, (tag, n)) in self.map.iter() {
+                seq.serialize_element(&(key, tag, n))?;
+            }
+            seq.end()
+        }
+    }
+
+    struct TotalsVisitor<R, Tag, N>(PhantomData<R>, PhantomData<Tag>, PhantomData<N>);
+
+    impl<'de, R, Tag, N> Visitor<'de> for TotalsVisitor<R, Tag, N>
+    where
+        R: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        N: CausalLength + Deserialize<'de>,
+    {
+        type Value = Totals<R, Tag, N>;
+
+        fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+            formatter.write_str("a tuple of key, tag, and total")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut map = HashMap::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(d) = seq.next_element::<(CounterKey<R>, Tag, N)>()? {
+                map.insert(d.0, (d.1, d.2));
+            }
+            Ok(Totals { map })
+        }
+    }
+
+    impl<'de, R, Tag, N> Deserialize<'de> for Totals<R, Tag, N>
+    where
+        R: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        N: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_seq(TotalsVisitor(PhantomData, PhantomData, PhantomData))
+        }
+    }
+
+    impl<R, Tag, N> Serialize for Counter<R, Tag, N>
+    where
+        R: Key + Serialize,
+        Tag: TagT + Serialize,
+        N: CausalLength + Signed + CheckedAdd + CheckedSub + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            self.totals.serialize(serializer)
+        }
+    }
+
+    impl<'de, R, Tag, N> Deserialize<'de> for Counter<R, Tag, N>
+    where
+        R: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        N: CausalLength + Signed + CheckedAdd + CheckedSub + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Counter {
+                totals: Totals::deserialize(deserializer)?,
+            })
+        }
+    }
+
+    impl<R, Tag, N> Serialize for BoundedCounter<R, Tag, N>
+    where
+        R: Key + Serialize,
+        Tag: TagT + Serialize,
+        N: CausalLength + Signed + CheckedAdd + CheckedSub + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            self.totals.serialize(serializer)
+        }
+    }
+
+    impl<'de, R, Tag, N> Deserialize<'de> for BoundedCounter<R, Tag, N>
+    where
+        R: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        N: CausalLength + Signed + CheckedAdd + CheckedSub + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(BoundedCounter {
+                totals: Totals::deserialize(deserializer)?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use rand::seq::SliceRandom;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_counter() {
+        let mut c1: Counter<&str, u32, i64> = Counter::new();
+        let mut c2: Counter<&str, u32, i64> = Counter::new();
+
+        let mut deltas = vec![
+            c1.increment("a", 5, 1).unwrap(),
+            c1.decrement("a", 2, 2).unwrap(),
+            c1.increment("a", 1, 3).unwrap(),
+            c2.increment("b", 10, 1).unwrap(),
+            c2.decrement("b", 20, 2).unwrap(),
+        ];
+        assert_eq!(c1.value(), Some(4));
+        assert_eq!(c2.value(), Some(-10));
+
+        // redundant and out of order
+        deltas.extend(deltas.clone());
+        deltas.shuffle(&mut rand::rng());
+        let mut c3: Counter<&str, u32, i64> = Counter::new();
+        for delta in deltas {
+            c3.merge_register(delta);
+        }
+        assert_eq!(c3.value(), Some(-6));
+
+        c1.merge(&c2);
+        assert_eq!(c1, c3);
+    }
+
+    #[test]
+    fn test_rejected_amounts() {
+        let mut c: Counter<&str, u32, i8> = Counter::new();
+        assert_eq!(c.increment("a", -1, 1), None);
+        assert_eq!(c.decrement("a", -1, 1), None);
+        c.increment("a", 100, 1).unwrap();
+        // the total would overflow
+        assert_eq!(c.increment("a", 100, 2), None);
+        assert_eq!(c.value(), Some(100));
+
+        let mut b: BoundedCounter<&str, u32, i8> = BoundedCounter::new();
+        b.increment("a", 10, 1).unwrap();
+        assert_eq!(b.decrement("a", -1, 2), None);
+        assert_eq!(b.transfer("a", "b", -1, 2), None);
+        assert_eq!(b.rights(&"a"), Some(10));
+    }
+
+    #[test]
+    fn test_bounded_counter() {
+        let mut c1: BoundedCounter<&str, u32, i64> = BoundedCounter::new();
+        c1.increment("a", 10, 1);
+        let mut c2 = c1.clone();
+
+        assert_eq!(c2.decrement("b", 1, 2), None);
+        let t = c1.transfer("a", "b", 4, 2).unwrap();
+        assert_eq!(c1.rights(&"a"), Some(6));
+        c2.merge_register(t);
+        assert_eq!(c2.rights(&"b"), Some(4));
+
+        // concurrent decrements never exceed the available rights
+        c1.decrement("a", 6, 3).unwrap();
+        assert_eq!(c1.decrement("a", 1, 3), None);
+        c2.decrement("b", 4, 3).unwrap();
+        c1.merge(&c2);
+        c2.merge(&c1);
+        assert_eq!(c1, c2);
+        assert_eq!(c1.value(), Some(0));
+    }
+
+    #[test]
+    fn test_value_overflow() {
+        let mut c: Counter<&str, u32, i8> = Counter::new();
+        c.increment("a", 100, 1).unwrap();
+        c.increment("b", 100, 1).unwrap();
+        assert_eq!(c.value(), None);
+        c.decrement("c", 100, 2).unwrap();
+        assert_eq!(c.value(), Some(100));
+
+        let mut b: BoundedCounter<&str, u32, i8> = BoundedCounter::new();
+        b.increment("a", 100, 1).unwrap();
+        b.increment("b", 100, 1).unwrap();
+        b.transfer("a", "c", 100, 2).unwrap();
+        b.transfer("b", "c", 100, 2).unwrap();
+        assert_eq!(b.rights(&"c"), None);
+        // more rights than fit in `N` can't be spent
+        assert_eq!(b.decrement("c", 100, 3), None);
+        assert_eq!(b.transfer("c", "a", 100, 3), None);
+        assert_eq!(b.rights(&"c"), None);
+    }
+
+    fn widen(delta: Register<CounterKey<u8>, u32, i8>) -> Register<CounterKey<u8>, u32, i64> {
+        Register::make(delta.item, delta.tag, i64::from(delta.length))
+    }
+
+    fn narrow(n: Option<i64>) -> Option<i8> {
+        n.and_then(|n| i8::try_from(n).ok())
+    }
+
+    // Small counters on several replicas agree with the same totals held in a wide type, or report
+    // the overflow
+    #[quickcheck]
+    fn is_value_checked(ops: Vec<(u8, u8, i8)>) -> bool {
+        let mut counter: Counter<u8, u32, i8> = Counter::new();
+        let mut wide_counter: Counter<u8, u32, i64> = Counter::new();
+        let mut bounded: BoundedCounter<u8, u32, i8> = BoundedCounter::new();
+        let mut wide_bounded: BoundedCounter<u8, u32, i64> = BoundedCounter::new();
+        for (tag, (replica, op, amount)) in ops.into_iter().enumerate() {
+            let (replica, tag) = (replica % 4, tag as u32);
+            let delta = match op % 2 {
+                0 => counter.increment(replica, amount, tag),
+                _ => counter.decrement(replica, amount, tag),
+            };
+            if let Some(delta) = delta {
+                wide_counter.merge_register(widen(delta));
+            }
+            let delta = match op % 3 {
+                0 => bounded.increment(replica, amount, tag),
+                1 => bounded.decrement(replica, amount, tag),
+                _ => bounded.transfer(replica, op % 4, amount, tag),
+            };
+            if let Some(delta) = delta {
+                wide_bounded.merge_register(widen(delta));
+            }
+        }
+        counter.value() == narrow(wide_counter.value())
+            && bounded.value() == narrow(wide_bounded.value())
+            && (0..4).all(|r| bounded.rights(&r) == narrow(wide_bounded.rights(&r)))
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut c: Counter<&str, u32, i64> = Counter::new();
+        c.increment("a", 5, 1);
+        c.decrement("b", 2, 2);
+
+        let data = serde_json::to_string(&c).unwrap();
+        let c2: Counter<&str, u32, i64> = serde_json::from_str(&data).unwrap();
+        assert_eq!(c, c2);
+    }
+}
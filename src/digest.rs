@@ -0,0 +1,359 @@
+use super::*;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+#[cfg(feature = "serialization")]
+use serde_derive::Serialize;
+
+/// Hasher with a fixed specification, the default for [Digest]
+///
+/// FNV-1a over the written bytes, with integers written little-endian and a final mix so the high
+/// bits used for buckets are well distributed. Equal values hash the same on every platform and
+/// with every version of Rust, so digests built by different builds can be compared.
+#[derive(Clone, Copy, Debug)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    // the same on 32 and 64 bit platforms
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        // the splitmix64 finalizer
+        let mut x = self.0;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+}
+
+/// Hash tree digest of a CRDT's deltas, for anti-entropy
+///
+/// Deltas are put in `2^depth` buckets by the hash of their key, and the buckets are the leaves of
+/// a binary hash tree. Two replicas exchange digests, find the buckets which differ with
+/// [Digest::diff], and exchange only the deltas in those buckets with [Digest::deltas_in]. Once
+/// each has merged the other's deltas, the replicas are equal.
+///
+/// Both replicas must build their digests with the same depth and hasher. The default hasher,
+/// [FnvHasher], is the same on every platform, but deltas are hashed through their `Hash`
+/// implementations, so those must agree too.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct Digest<H = BuildHasherDefault<FnvHasher>> {
+    depth: u32,
+    // implicit binary tree: the root is at 1, children of `i` at `2i` and `2i+1`, leaves last
+    nodes: Vec<u64>,
+    #[cfg_attr(feature = "serialization", serde(skip))]
+    hasher: H,
+}
+
+impl<H> PartialEq for Digest<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth == other.depth && self.nodes == other.nodes
+    }
+}
+
+impl<H> Eq for Digest<H> {}
+
+impl<H> Digest<H> {
+    /// The greatest depth of a digest, with 16M buckets
+    pub const MAX_DEPTH: u32 = 24;
+}
+
+impl Digest {
+    /// Build the digest of a CRDT, with `2^depth` buckets
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is greater than [Digest::MAX_DEPTH].
+    pub fn new<C>(crdt: &C, depth: u32) -> Digest
+    where
+        C: Coalesce,
+        C::Delta: Hash,
+    {
+        Digest::with_hasher(crdt, depth, BuildHasherDefault::default())
+    }
+}
+
+impl<H> Digest<H>
+where
+    H: BuildHasher,
+{
+    /// Build the digest of a CRDT, with `2^depth` buckets, using the given hasher
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is greater than [Digest::MAX_DEPTH].
+    pub fn with_hasher<C>(crdt: &C, depth: u32, hasher: H) -> Digest<H>
+    where
+        C: Coalesce,
+        C::Delta: Hash,
+    {
+        assert!(
+            depth <= Self::MAX_DEPTH,
+            "digest depth (is {}) should be <= {}",
+            depth,
+            Self::MAX_DEPTH
+        );
+        let leaves = 1 << depth;
+        let mut digest = Digest {
+            depth,
+            nodes: vec![0; 2 * leaves],
+            hasher,
+        };
+        for delta in crdt.deltas() {
+            let leaf = leaves + digest.bucket(&C::delta_key(&delta));
+            // order independent, as deltas are visited in arbitrary order
+            digest.nodes[leaf] = digest.nodes[leaf].wrapping_add(digest.hash(&delta));
+        }
+        for i in (1..leaves).rev() {
+            digest.nodes[i] = digest.hash(&(digest.nodes[2 * i], digest.nodes[2 * i + 1]));
+        }
+        digest
+    }
+
+    fn hash<T: Hash>(&self, value: &T) -> u64 {
+        self.hasher.hash_one(value)
+    }
+
+    /// Returns the bucket deltas for `key` are put in.
+    pub fn bucket<K: Hash>(&self, key: &K) -> usize {
+        match self.depth {
+            0 => 0,
+            depth => (self.hash(key) >> (64 - depth)) as usize,
+        }
+    }
+
+    /// Returns the hash at the root of the tree, which is equal for equal replicas.
+    pub fn root(&self) -> u64 {
+        self.nodes[1]
+    }
+
+    /// Returns the buckets which differ between two digests, in ascending order.
+    ///
+    /// Only subtrees with differing hashes are visited.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the digests have different depths.
+    pub fn diff(&self, other: &Self) -> Vec<usize> {
+        assert_eq!(self.depth, other.depth, "digest depths should be equal");
+        let leaves = 1 << self.depth;
+        let mut buckets = Vec::new();
+        let mut pending = vec![1];
+        while let Some(i) = pending.pop() {
+            if self.nodes[i] == other.nodes[i] {
+                continue;
+            }
+            if i >= leaves {
+                buckets.push(i - leaves);
+            } else {
+                pending.extend([2 * i + 1, 2 * i].iter().copied());
+            }
+        }
+        buckets.sort_unstable();
+        buckets
+    }
+
+    /// An iterator visiting the deltas of `crdt` which are in any of `buckets`.
+    ///
+    /// `buckets` must be in ascending order, as returned by [Digest::diff].
+    pub fn deltas_in<'a, C>(
+        &'a self,
+        crdt: &'a C,
+        buckets: &'a [usize],
+    ) -> impl Iterator<Item = C::Delta> + 'a
+    where
+        C: Coalesce,
+    {
+        crdt.deltas().filter(move |delta| {
+            buckets
+                .binary_search(&self.bucket(&C::delta_key(delta)))
+                .is_ok()
+        })
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer};
+
+    impl<'de, H> Deserialize<'de> for Digest<H>
+    where
+        H: Default,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(serde_derive::Deserialize)]
+            struct Raw {
+                depth: u32,
+                nodes: Vec<u64>,
+            }
+
+            let Raw { depth, nodes } = Raw::deserialize(deserializer)?;
+            if depth > Self::MAX_DEPTH {
+                return Err(D::Error::custom(format!(
+                    "digest depth (is {}) should be <= {}",
+                    depth,
+                    Self::MAX_DEPTH
+                )));
+            }
+            if nodes.len() != 2 << depth {
+                return Err(D::Error::custom(format!(
+                    "digest of depth {} should have {} nodes, not {}",
+                    depth,
+                    2 << depth,
+                    nodes.len()
+                )));
+            }
+            Ok(Digest {
+                depth,
+                nodes,
+                hasher: H::default(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync<C>(a: &mut C, b: &mut C) -> usize
+    where
+        C: Coalesce,
+        C::Delta: Hash,
+    {
+        let (da, db) = (Digest::new(a, 6), Digest::new(b, 6));
+        let buckets = da.diff(&db);
+        let to_b: Vec<_> = da.deltas_in(a, &buckets).collect();
+        let to_a: Vec<_> = db.deltas_in(b, &buckets).collect();
+        let sent = to_a.len() + to_b.len();
+        for delta in to_a {
+            a.apply_delta(delta);
+        }
+        for delta in to_b {
+            b.apply_delta(delta);
+        }
+        sent
+    }
+
+    #[test]
+    fn test_sync_set() {
+        let mut a: Set<u32, u32, u16> = Set::new();
+        for i in 0..1000 {
+            a.add(i, 1);
+        }
+        let mut b = a.clone();
+        assert_eq!(Digest::new(&a, 6), Digest::new(&b, 6));
+        assert_eq!(sync(&mut a, &mut b), 0);
+
+        a.remove(7, 2);
+        b.add(1000, 2);
+        let (da, db) = (Digest::new(&a, 6), Digest::new(&b, 6));
+        assert_ne!(da.root(), db.root());
+        let buckets = da.diff(&db);
+        assert!(!buckets.is_empty() && buckets.len() <= 2);
+
+        // only the registers in differing buckets are exchanged
+        assert!(sync(&mut a, &mut b) < 100);
+        assert_eq!(a, b);
+        assert!(!a.contains(7));
+        assert!(a.contains(1000));
+    }
+
+    #[test]
+    fn test_sync_map() {
+        let mut a: Map<u32, u32, u32, u16> = Map::new();
+        let mut b = Map::new();
+        for i in 0..100 {
+            a.insert(i, i, 1);
+            b.insert(i, i, 1);
+        }
+        a.insert(5, 50, 2);
+        b.insert(5, 500, 3);
+        b.remove(6, 3);
+        sync(&mut a, &mut b);
+        assert_eq!(a, b);
+        assert_eq!(a.get(5).map(|(v, _)| *v), Some(500));
+        assert!(Digest::new(&a, 0).diff(&Digest::new(&b, 0)).is_empty());
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut set: Set<u8, u32, u16> = Set::new();
+        set.add(1, 1);
+        let digest = Digest::new(&set, 4);
+
+        let data = serde_json::to_string(&digest).unwrap();
+        let digest2: Digest = serde_json::from_str(&data).unwrap();
+        assert_eq!(digest, digest2);
+        assert_eq!(digest2.bucket(&1u8), digest.bucket(&1u8));
+
+        // malformed digests are rejected rather than panicking later
+        assert!(serde_json::from_str::<Digest>(r#"{"depth":1,"nodes":[0,0]}"#).is_err());
+        assert!(serde_json::from_str::<Digest>(r#"{"depth":40,"nodes":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_fixed_hash() {
+        // pinned, so a change to the hash that would break digests across builds is caught
+        let hasher = BuildHasherDefault::<FnvHasher>::default();
+        assert_eq!(hasher.hash_one(0u64), hasher.hash_one(0i64));
+        assert_eq!(hasher.hash_one(1usize), hasher.hash_one(1u64));
+        assert_eq!(hasher.hash_one("foo"), 15762442136122190272);
+    }
+}
@@ -0,0 +1,494 @@
+use super::*;
+use crate::map::OrderedMap;
+use crate::register::Register;
+use crate::set::OrderedSet;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Number, Value as Json};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Path to a node of a [Document], as a list of object keys
+pub type Path = Vec<String>;
+
+/// A node of a [Document], as stored at a path
+///
+/// Objects and arrays are stored as markers, with their contents stored at longer paths.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum Node {
+    /// JSON `null`
+    Null,
+    /// JSON boolean
+    Bool(bool),
+    /// JSON number, kept as text, as `Number` isn't `Ord`
+    Number(String),
+    /// JSON string
+    String(String),
+    /// Object marker, its members are stored at the paths below it
+    Object,
+    /// Array marker, its scalar elements are stored as a set, keyed by the same path
+    Array,
+}
+
+impl Node {
+    fn scalar(value: &Json) -> Option<Node> {
+        match value {
+            Json::Null => Some(Node::Null),
+            Json::Bool(b) => Some(Node::Bool(*b)),
+            Json::Number(n) => Some(Node::Number(n.to_string())),
+            Json::String(s) => Some(Node::String(s.clone())),
+            Json::Array(_) | Json::Object(_) => None,
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Node::Null => Json::Null,
+            Node::Bool(b) => Json::Bool(*b),
+            Node::Number(n) => n.parse::<Number>().map(Json::Number).unwrap_or(Json::Null),
+            Node::String(s) => Json::String(s.clone()),
+            Node::Object => Json::Object(Default::default()),
+            Node::Array => Json::Array(Vec::new()),
+        }
+    }
+}
+
+/// Error returned by [Document] operations which take JSON values
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DocumentError {
+    /// Arrays are replicated as sets, so may only hold scalars.
+    NonScalarElement(Path),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::NonScalarElement(path) => {
+                write!(f, "array at /{} holds a non-scalar element", path.join("/"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+/// Delta for a [Document], addressed by path
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DocumentDelta<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Node stored at a path, a [Map] delta
+    Node(Register<(Path, Node), Tag, CL>),
+    /// Element of the array at a path, a [Set] delta
+    Element(Register<(Path, Node), Tag, CL>),
+}
+
+/// Causal Length JSON Document
+///
+/// A JSON document, stored as an [OrderedMap] from each path to the node there, and an
+/// [OrderedSet] of array elements. Every path merges with [Map::merge_register] semantics, so
+/// concurrent edits of different paths all survive. Arrays are treated as sets of scalars, and read
+/// back in a deterministic order rather than insertion order.
+///
+/// A path with visible contents below it reads as an object, unless it holds an array, so
+/// concurrently setting a scalar and a child of the same path keeps the child.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Document<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    nodes: OrderedMap<Path, Node, Tag, CL>,
+    elements: OrderedSet<(Path, Node), Tag, CL>,
+}
+
+impl<Tag, CL> Default for Document<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+fn to_path(path: &[&str]) -> Path {
+    path.iter().map(|s| s.to_string()).collect()
+}
+
+impl<Tag, CL> Document<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new empty `Document`
+    pub fn new() -> Document<Tag, CL> {
+        Document {
+            nodes: OrderedMap::new(),
+            elements: OrderedSet::new(),
+        }
+    }
+
+    /// Returns the value at `path`, or `None` if nothing is stored there.
+    pub fn get(&self, path: &[&str]) -> Option<Json> {
+        self.build(&to_path(path))
+    }
+
+    /// Materialize the whole document as JSON.
+    pub fn to_json(&self) -> Json {
+        self.build(&Vec::new()).unwrap_or(Json::Null)
+    }
+
+    /// Replace the value at `path`, creating objects above it as needed.
+    ///
+    /// Returns the deltas to merge into other replicas, or an error if `value` holds an array with
+    /// non-scalar elements, in which case the document is left unchanged.
+    pub fn set(
+        &mut self,
+        path: &[&str],
+        value: &Json,
+        tag: Tag,
+    ) -> Result<Vec<DocumentDelta<Tag, CL>>, DocumentError> {
+        self.set_path(to_path(path), value, tag)
+    }
+
+    /// Delete the value at `path`, and everything below it.
+    ///
+    /// Returns the deltas to merge into other replicas.
+    pub fn delete(&mut self, path: &[&str], tag: Tag) -> Vec<DocumentDelta<Tag, CL>> {
+        self.delete_path(&to_path(path), tag)
+    }
+
+    /// Add a scalar to the array at `path`, replacing any other value there with an array.
+    ///
+    /// Returns the deltas to merge into other replicas, or an error if `value` isn't a scalar.
+    pub fn add_element(
+        &mut self,
+        path: &[&str],
+        value: &Json,
+        tag: Tag,
+    ) -> Result<Vec<DocumentDelta<Tag, CL>>, DocumentError> {
+        let path = to_path(path);
+        let element =
+            Node::scalar(value).ok_or_else(|| DocumentError::NonScalarElement(path.clone()))?;
+        let mut deltas = Vec::new();
+        if !matches!(self.nodes.get(&path), Some((Node::Array, _))) {
+            deltas = self.set_path(path.clone(), &Json::Array(Vec::new()), tag)?;
+        }
+        deltas.push(DocumentDelta::Element(
+            self.elements.add((path, element), tag),
+        ));
+        Ok(deltas)
+    }
+
+    /// Remove a scalar from the array at `path`.
+    ///
+    /// Returns the delta to merge into other replicas, or `None` if `value` isn't in the array.
+    pub fn remove_element(
+        &mut self,
+        path: &[&str],
+        value: &Json,
+        tag: Tag,
+    ) -> Option<DocumentDelta<Tag, CL>> {
+        let element = Node::scalar(value)?;
+        self.elements
+            .remove((to_path(path), element), tag)
+            .map(DocumentDelta::Element)
+    }
+
+    /// An iterator visiting all deltas, in path order.
+    pub fn delta_iter(&self) -> impl Iterator<Item = DocumentDelta<Tag, CL>> + '_ {
+        self.nodes
+            .register_iter()
+            .map(DocumentDelta::Node)
+            .chain(self.elements.register_iter().map(DocumentDelta::Element))
+    }
+
+    /// Merge a delta into the document.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    ///
+    /// Returns the visible effect the delta had on the node or array element at its path.
+    pub fn merge_delta(
+        &mut self,
+        delta: DocumentDelta<Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<Path, Node> {
+        match delta {
+            DocumentDelta::Node(delta) => self.nodes.merge_register_report(delta, min_tag),
+            DocumentDelta::Element(delta) => {
+                let (path, element) = delta.item.clone();
+                match self.elements.merge_register_report(delta, min_tag) {
+                    Change::Added(..) => Change::Added(path, element),
+                    Change::Removed(..) => Change::Removed(path, element),
+                    Change::Ignored(_) => Change::Ignored(path),
+                    _ => Change::Unchanged(path),
+                }
+            }
+        }
+    }
+
+    /// Merge two documents.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        self.nodes.merge(&other.nodes, min_tag);
+        self.elements.merge(&other.elements, min_tag);
+    }
+
+    /// Filter out old remove tombstone deltas from the document.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.nodes.retain(min_tag);
+        self.elements.retain(min_tag);
+    }
+
+    fn build(&self, path: &Path) -> Option<Json> {
+        let node = self.nodes.get(path).map(|(node, _)| node.clone());
+        if node == Some(Node::Array) {
+            return Some(Json::Array(
+                self.elements
+                    .range((path.clone(), Node::Null)..)
+                    .take_while(|((p, _), _)| p == path)
+                    .map(|((_, element), _)| element.to_json())
+                    .collect(),
+            ));
+        }
+
+        let depth = path.len();
+        let names: BTreeSet<String> = self
+            .nodes
+            .range(path.clone()..)
+            .map(|(p, _, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .chain(
+                self.elements
+                    .range((path.clone(), Node::Null)..)
+                    .map(|((p, _), _)| p.clone())
+                    .take_while(|p| p.starts_with(path)),
+            )
+            .filter(|p| p.len() > depth)
+            .map(|p| p[depth].clone())
+            .collect();
+        let mut children = serde_json::Map::new();
+        for name in names {
+            let mut child = path.clone();
+            child.push(name.clone());
+            if let Some(value) = self.build(&child) {
+                children.insert(name, value);
+            }
+        }
+        if !children.is_empty() || node == Some(Node::Object) {
+            return Some(Json::Object(children));
+        }
+        node.map(|node| node.to_json())
+    }
+
+    fn set_path(
+        &mut self,
+        path: Path,
+        value: &Json,
+        tag: Tag,
+    ) -> Result<Vec<DocumentDelta<Tag, CL>>, DocumentError> {
+        check(&path, value)?;
+        let mut deltas = self.delete_path(&path, tag);
+        for i in 0..path.len() {
+            let ancestor = path[..i].to_vec();
+            if !matches!(self.nodes.get(&ancestor), Some((Node::Object, _))) {
+                deltas.push(DocumentDelta::Node(self.nodes.insert(
+                    ancestor,
+                    Node::Object,
+                    tag,
+                )));
+            }
+        }
+        self.store(path, value, tag, &mut deltas);
+        Ok(deltas)
+    }
+
+    fn delete_path(&mut self, path: &Path, tag: Tag) -> Vec<DocumentDelta<Tag, CL>> {
+        let nodes: Vec<Path> = self
+            .nodes
+            .range(path.clone()..)
+            .take_while(|(p, _, _)| p.starts_with(path))
+            .map(|(p, _, _)| p)
+            .collect();
+        let elements: Vec<(Path, Node)> = self
+            .elements
+            .range((path.clone(), Node::Null)..)
+            .take_while(|((p, _), _)| p.starts_with(path))
+            .map(|(e, _)| e.clone())
+            .collect();
+        let mut deltas: Vec<DocumentDelta<Tag, CL>> = nodes
+            .into_iter()
+            .filter_map(|p| self.nodes.remove(p, tag))
+            .map(DocumentDelta::Node)
+            .collect();
+        deltas.extend(
+            elements
+                .into_iter()
+                .filter_map(|e| self.elements.remove(e, tag))
+                .map(DocumentDelta::Element),
+        );
+        deltas
+    }
+
+    fn store(
+        &mut self,
+        path: Path,
+        value: &Json,
+        tag: Tag,
+        deltas: &mut Vec<DocumentDelta<Tag, CL>>,
+    ) {
+        match value {
+            Json::Object(map) => {
+                for (key, child) in map {
+                    let mut child_path = path.clone();
+                    child_path.push(key.clone());
+                    self.store(child_path, child, tag, deltas);
+                }
+                deltas.push(DocumentDelta::Node(self.nodes.insert(
+                    path,
+                    Node::Object,
+                    tag,
+                )));
+            }
+            Json::Array(items) => {
+                for item in items.iter().filter_map(Node::scalar) {
+                    deltas.push(DocumentDelta::Element(
+                        self.elements.add((path.clone(), item), tag),
+                    ));
+                }
+                deltas.push(DocumentDelta::Node(self.nodes.insert(
+                    path,
+                    Node::Array,
+                    tag,
+                )));
+            }
+            _ => {
+                if let Some(node) = Node::scalar(value) {
+                    deltas.push(DocumentDelta::Node(self.nodes.insert(path, node, tag)));
+                }
+            }
+        }
+    }
+}
+
+fn check(path: &Path, value: &Json) -> Result<(), DocumentError> {
+    match value {
+        Json::Object(map) => map.iter().try_for_each(|(key, child)| {
+            let mut child_path = path.clone();
+            child_path.push(key.clone());
+            check(&child_path, child)
+        }),
+        Json::Array(items) if items.iter().any(|item| Node::scalar(item).is_none()) => {
+            Err(DocumentError::NonScalarElement(path.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl<Tag, CL> DeltaCrdt for Document<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = DocumentDelta<Tag, CL>;
+
+    fn bottom() -> Self {
+        Document::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.merge_delta(delta, Tag::default()).is_visible()
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.delta_iter()
+    }
+}
+
+impl<Tag, CL> Tagged<Tag> for DocumentDelta<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn max_tag(&self) -> Option<Tag> {
+        match self {
+            DocumentDelta::Node(register) | DocumentDelta::Element(register) => Some(register.tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use serde_json::json;
+
+    #[test]
+    fn test_set_and_get() {
+        let mut doc: Document<u32, u16> = Document::new();
+        let config =
+            json!({"name": "app", "debug": false, "ports": [443, 80], "db": {"host": "x"}});
+        doc.set(&[], &config, 1).unwrap();
+        assert_eq!(doc.to_json(), config);
+
+        doc.set(&["db", "port"], &json!(5432), 2).unwrap();
+        doc.delete(&["debug"], 2);
+        assert_eq!(doc.get(&["db"]), Some(json!({"host": "x", "port": 5432})));
+        assert_eq!(doc.get(&["debug"]), None);
+
+        // setting below a scalar replaces it with an object
+        doc.set(&["name", "short"], &json!("a"), 3).unwrap();
+        assert_eq!(doc.get(&["name"]), Some(json!({"short": "a"})));
+
+        assert_eq!(
+            doc.set(&["bad"], &json!([{"a": 1}]), 4),
+            Err(DocumentError::NonScalarElement(vec!["bad".to_string()]))
+        );
+        assert_eq!(doc.get(&["bad"]), None);
+    }
+
+    #[test]
+    fn test_concurrent_edits() {
+        let mut d1: Document<u32, u16> = Document::new();
+        let mut deltas = d1
+            .set(&[], &json!({"tags": ["a"], "db": {"host": "x"}}), 1)
+            .unwrap();
+        let mut d2 = d1.clone();
+
+        deltas.extend(d1.set(&["db", "host"], &json!("y"), 2).unwrap());
+        deltas.extend(d1.add_element(&["tags"], &json!("b"), 2).unwrap());
+        deltas.extend(d2.set(&["db", "user"], &json!("root"), 2).unwrap());
+        deltas.extend(d2.add_element(&["tags"], &json!("c"), 2).unwrap());
+        deltas.extend(d2.remove_element(&["tags"], &json!("a"), 2));
+
+        d1.merge(&d2, 0);
+        d2.merge(&d1, 0);
+        assert_eq!(d1, d2);
+        assert_eq!(
+            d1.to_json(),
+            json!({"tags": ["b", "c"], "db": {"host": "y", "user": "root"}})
+        );
+        replay(&d1, deltas);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut doc: Document<u32, u16> = Document::new();
+        doc.set(&[], &json!({"a": [1, 2], "b": {"c": null}}), 1)
+            .unwrap();
+        doc.delete(&["b"], 2);
+
+        let data = serde_json::to_string(&doc).unwrap();
+        let doc2: Document<u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(doc, doc2);
+    }
+}
@@ -0,0 +1,244 @@
+use super::*;
+use crate::register::Register;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Enable-wins flag
+///
+/// A [Register] without an item: odd causal length means enabled. Enabling an enabled flag still
+/// advances the causal length, the same way [Register::set] does, so an enable concurrent with a
+/// disable wins. Starts disabled.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct EnableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    register: Register<(), Tag, CL>,
+}
+
+impl<Tag, CL> EnableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new disabled `EnableWins` flag
+    pub fn new() -> EnableWins<Tag, CL> {
+        EnableWins {
+            register: Register::make((), Tag::default(), CL::zero()),
+        }
+    }
+
+    /// Returns true if the flag is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.register.length.is_odd()
+    }
+
+    /// Enable the flag.
+    ///
+    /// Returns the delta [Register] to merge into other replicas.
+    pub fn enable(&mut self, tag: Tag) -> Register<(), Tag, CL> {
+        self.register.set((), tag)
+    }
+
+    /// Disable the flag.
+    ///
+    /// Returns the delta [Register] to merge into other replicas.
+    pub fn disable(&mut self, tag: Tag) -> Register<(), Tag, CL> {
+        self.register.clear(tag);
+        self.register.clone()
+    }
+
+    /// Merge a delta [Register] into the flag.
+    pub fn merge_register(&mut self, delta: &Register<(), Tag, CL>) {
+        self.register.merge(delta);
+    }
+
+    /// Merge two flags.
+    pub fn merge(&mut self, other: &Self) {
+        self.register.merge(&other.register);
+    }
+}
+
+/// Disable-wins flag
+///
+/// A [Register] without an item, where the item stands for the flag being disabled: even non-zero
+/// causal length means enabled. Disabling a disabled flag still advances the causal length, so a
+/// disable concurrent with an enable wins. Starts disabled.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct DisableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    register: Register<(), Tag, CL>,
+}
+
+impl<Tag, CL> DisableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new disabled `DisableWins` flag
+    pub fn new() -> DisableWins<Tag, CL> {
+        DisableWins {
+            register: Register::make((), Tag::default(), CL::zero()),
+        }
+    }
+
+    /// Returns true if the flag is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.register.length.is_even() && !self.register.length.is_zero()
+    }
+
+    /// Enable the flag.
+    ///
+    /// Returns the delta [Register] to merge into other replicas.
+    pub fn enable(&mut self, tag: Tag) -> Register<(), Tag, CL> {
+        if self.register.length.is_zero() {
+            self.register.set((), tag);
+        }
+        self.register.clear(tag);
+        self.register.clone()
+    }
+
+    /// Disable the flag.
+    ///
+    /// Returns the delta [Register] to merge into other replicas.
+    pub fn disable(&mut self, tag: Tag) -> Register<(), Tag, CL> {
+        if self.register.length.is_zero() {
+            // must outrun an enable from the initial state, which skips to 2
+            self.register.set((), tag);
+        }
+        self.register.set((), tag)
+    }
+
+    /// Merge a delta [Register] into the flag.
+    pub fn merge_register(&mut self, delta: &Register<(), Tag, CL>) {
+        self.register.merge(delta);
+    }
+
+    /// Merge two flags.
+    pub fn merge(&mut self, other: &Self) {
+        self.register.merge(&other.register);
+    }
+}
+
+impl<Tag, CL> DeltaCrdt for EnableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<(), Tag, CL>;
+
+    fn bottom() -> Self {
+        EnableWins::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        let before = self.is_enabled();
+        self.merge_register(&delta);
+        before != self.is_enabled()
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        std::iter::once(self.register.clone())
+    }
+}
+
+impl<Tag, CL> DeltaCrdt for DisableWins<Tag, CL>
+where
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<(), Tag, CL>;
+
+    fn bottom() -> Self {
+        DisableWins::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        let before = self.is_enabled();
+        self.merge_register(&delta);
+        before != self.is_enabled()
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        std::iter::once(self.register.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enable_wins() {
+        let mut f1: EnableWins<u32, u16> = EnableWins::new();
+        assert!(!f1.is_enabled());
+        f1.enable(1);
+        let mut f2 = f1.clone();
+
+        f1.disable(2);
+        let delta = f2.enable(2);
+        assert!(!f1.is_enabled());
+        f1.merge_register(&delta);
+        f2.merge(&f1);
+        assert!(f1.is_enabled());
+        assert_eq!(f1, f2);
+
+        // concurrent enable and redundant disable from the initial state
+        let mut f3: EnableWins<u32, u16> = EnableWins::new();
+        let mut f4 = f3.clone();
+        f3.enable(1);
+        f4.disable(1);
+        f4.merge(&f3);
+        assert!(f4.is_enabled());
+    }
+
+    #[test]
+    fn test_disable_wins() {
+        let mut f1: DisableWins<u32, u16> = DisableWins::new();
+        assert!(!f1.is_enabled());
+        let mut f2 = f1.clone();
+
+        // from the initial state
+        f1.enable(1);
+        assert!(f1.is_enabled());
+        let delta = f2.disable(1);
+        f1.merge_register(&delta);
+        assert!(!f1.is_enabled());
+
+        f1.enable(2);
+        f2.merge(&f1);
+        assert!(f2.is_enabled());
+        f1.disable(3);
+        f2.enable(3);
+        f1.merge(&f2);
+        f2.merge(&f1);
+        assert!(!f1.is_enabled());
+        assert_eq!(f1, f2);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut f: DisableWins<u32, u16> = DisableWins::new();
+        f.enable(1);
+
+        let data = serde_json::to_string(&f).unwrap();
+        let f2: DisableWins<u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(f, f2);
+    }
+}
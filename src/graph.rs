@@ -0,0 +1,434 @@
+use super::*;
+use crate::register::Register;
+use crate::set::Set;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// A vertex or directed edge of a [Graph]
+///
+/// [Graph] deltas are [Register]s over graph items.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum GraphItem<V> {
+    /// A vertex
+    Vertex(V),
+    /// A directed edge, from the first vertex to the second
+    Edge(V, V),
+}
+
+/// Causal Length Graph
+///
+/// A directed graph made of two causal length [Set]s, one of vertices and one of edges. An edge
+/// is only visible while both of its endpoints are.
+///
+/// Removing a vertex also removes the edges the removing replica can see, so adding the vertex
+/// again starts without them. An edge added concurrently with the remove isn't removed: it stays
+/// hidden while its endpoint is removed, and becomes visible again if the endpoint is added again.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Graph<V, Tag, CL>
+where
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    vertices: Set<V, Tag, CL>,
+    edges: Set<(V, V), Tag, CL>,
+    // targets and sources of the edges in `edges`, whether or not their endpoints are visible
+    successors: HashMap<V, HashSet<V>>,
+    predecessors: HashMap<V, HashSet<V>>,
+}
+
+fn vertex_delta<V, Tag, CL>(r: Register<V, Tag, CL>) -> Register<GraphItem<V>, Tag, CL>
+where
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    Register::make(GraphItem::Vertex(r.item), r.tag, r.length)
+}
+
+fn edge_delta<V, Tag, CL>(r: Register<(V, V), Tag, CL>) -> Register<GraphItem<V>, Tag, CL>
+where
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    let (from, to) = r.item;
+    Register::make(GraphItem::Edge(from, to), r.tag, r.length)
+}
+
+impl<V, Tag, CL> Graph<V, Tag, CL>
+where
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new empty `Graph`
+    pub fn new() -> Graph<V, Tag, CL> {
+        Graph {
+            vertices: Set::new(),
+            edges: Set::new(),
+            successors: HashMap::new(),
+            predecessors: HashMap::new(),
+        }
+    }
+
+    // Bring the adjacency index up to date with the edge set, after a change to the edge.
+    fn index_edge(&mut self, from: V, to: V) {
+        let edge = (from, to);
+        let present = self.edges.contains(&edge);
+        let (from, to) = edge;
+        if present {
+            self.predecessors
+                .entry(to.clone())
+                .or_default()
+                .insert(from.clone());
+            self.successors.entry(from).or_default().insert(to);
+        } else {
+            unlink(&mut self.successors, &from, &to);
+            unlink(&mut self.predecessors, &to, &from);
+        }
+    }
+
+    // Endpoints adjacent to `v` in `index`, which are visible along with `v`.
+    fn adjacent<'a>(
+        &'a self,
+        index: &'a HashMap<V, HashSet<V>>,
+        v: &'a V,
+    ) -> impl Iterator<Item = &'a V> + 'a {
+        let visible = self.contains_vertex(v);
+        index
+            .get(v)
+            .into_iter()
+            .flatten()
+            .filter(move |w| visible && self.contains_vertex(w))
+    }
+
+    /// Returns true if the vertex is visible.
+    pub fn contains_vertex(&self, v: &V) -> bool {
+        self.vertices.contains(v)
+    }
+
+    /// Returns true if the edge and both of its endpoints are visible.
+    pub fn contains_edge(&self, from: &V, to: &V) -> bool {
+        self.contains_vertex(from)
+            && self.contains_vertex(to)
+            && self.edges.contains(&(from.clone(), to.clone()))
+    }
+
+    /// An iterator visiting all visible vertices in arbitrary order.
+    pub fn vertices(&self) -> impl Iterator<Item = &V> + '_ {
+        self.vertices.iter().map(|(v, _)| v)
+    }
+
+    /// An iterator visiting all visible edges in arbitrary order.
+    pub fn edges(&self) -> impl Iterator<Item = (&V, &V)> + '_ {
+        self.edges
+            .iter()
+            .map(|((from, to), _)| (from, to))
+            .filter(move |(from, to)| self.contains_vertex(from) && self.contains_vertex(to))
+    }
+
+    /// An iterator visiting the targets of visible edges from `v`.
+    ///
+    /// Only visits the edges from `v`, using an index of edges by endpoint.
+    pub fn successors<'a>(&'a self, v: &'a V) -> impl Iterator<Item = &'a V> + 'a {
+        self.adjacent(&self.successors, v)
+    }
+
+    /// An iterator visiting the sources of visible edges to `v`.
+    ///
+    /// Only visits the edges to `v`, using an index of edges by endpoint.
+    pub fn predecessors<'a>(&'a self, v: &'a V) -> impl Iterator<Item = &'a V> + 'a {
+        self.adjacent(&self.predecessors, v)
+    }
+
+    /// Add a vertex.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    pub fn add_vertex(&mut self, v: V, tag: Tag) -> Register<GraphItem<V>, Tag, CL> {
+        vertex_delta(self.vertices.add(v, tag))
+    }
+
+    /// Remove a vertex, along with its edges, so adding it again starts without them.
+    ///
+    /// Edges added concurrently by other replicas aren't removed, see [Graph].
+    ///
+    /// Returns the delta [Register]s that reproduce this change when merged into another replica.
+    pub fn remove_vertex(&mut self, v: V, tag: Tag) -> Vec<Register<GraphItem<V>, Tag, CL>> {
+        let outgoing = self.successors.get(&v).into_iter().flatten();
+        let incoming = self.predecessors.get(&v).into_iter().flatten();
+        let incident: Vec<(V, V)> = outgoing
+            .map(|to| (v.clone(), to.clone()))
+            .chain(incoming.map(|from| (from.clone(), v.clone())))
+            .collect();
+        let mut deltas = Vec::new();
+        for (from, to) in incident {
+            if let Some(delta) = self.edges.remove((from.clone(), to.clone()), tag) {
+                deltas.push(edge_delta(delta));
+            }
+            self.index_edge(from, to);
+        }
+        deltas.extend(self.vertices.remove(v, tag).map(vertex_delta));
+        deltas
+    }
+
+    /// Add an edge between two visible vertices.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if either endpoint isn't visible.
+    pub fn add_edge(
+        &mut self,
+        from: V,
+        to: V,
+        tag: Tag,
+    ) -> Option<Register<GraphItem<V>, Tag, CL>> {
+        if !self.contains_vertex(&from) || !self.contains_vertex(&to) {
+            return None;
+        }
+        let delta = self.edges.add((from.clone(), to.clone()), tag);
+        self.index_edge(from, to);
+        Some(edge_delta(delta))
+    }
+
+    /// Remove an edge.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if the edge isn't in the graph.
+    pub fn remove_edge(
+        &mut self,
+        from: V,
+        to: V,
+        tag: Tag,
+    ) -> Option<Register<GraphItem<V>, Tag, CL>> {
+        let delta = self.edges.remove((from.clone(), to.clone()), tag);
+        self.index_edge(from, to);
+        delta.map(edge_delta)
+    }
+
+    /// An iterator visiting all registers, vertices first.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<GraphItem<V>, Tag, CL>> + '_ {
+        self.vertices
+            .register_iter()
+            .map(vertex_delta)
+            .chain(self.edges.register_iter().map(edge_delta))
+    }
+
+    /// Merge a delta [Register] into the graph.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    pub fn merge_register(&mut self, delta: Register<GraphItem<V>, Tag, CL>, min_tag: Tag) {
+        if delta.length.is_even() && delta.tag < min_tag {
+            // ignore excessively old remove records
+            return;
+        }
+        self.join_register(delta);
+    }
+
+    // Returns true if the vertex or edge became present or absent in its set.
+    fn join_register(&mut self, delta: Register<GraphItem<V>, Tag, CL>) -> bool {
+        let Register { item, tag, length } = delta;
+        match item {
+            GraphItem::Vertex(v) => self.vertices.apply_delta(Register::make(v, tag, length)),
+            GraphItem::Edge(from, to) => {
+                let visible =
+                    self.edges
+                        .apply_delta(Register::make((from.clone(), to.clone()), tag, length));
+                self.index_edge(from, to);
+                visible
+            }
+        }
+    }
+
+    /// Merge a delta [Register] into the graph, see [Graph::merge_register].
+    ///
+    /// Returns the effect the delta had on the vertex or edge set. Edges hidden by their endpoints
+    /// are still reported.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<GraphItem<V>, Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<GraphItem<V>> {
+        if delta.length.is_even() && delta.tag < min_tag {
+            return Change::Ignored(delta.item);
+        }
+        let item = delta.item.clone();
+        let removed = delta.length.is_even();
+        if !self.join_register(delta) {
+            Change::Unchanged(item)
+        } else if removed {
+            Change::Removed(item, ())
+        } else {
+            Change::Added(item, ())
+        }
+    }
+
+    /// Merge two graphs.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        self.vertices.merge(&other.vertices, min_tag);
+        for delta in other.edges.register_iter() {
+            let (from, to) = delta.item.clone();
+            self.edges.merge_register(delta, min_tag);
+            self.index_edge(from, to);
+        }
+    }
+
+    /// Filter out old remove tombstone deltas from the graph.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        // only removed edges are dropped, so the index is unchanged
+        self.vertices.retain(min_tag);
+        self.edges.retain(min_tag);
+    }
+}
+
+fn unlink<V: Key>(index: &mut HashMap<V, HashSet<V>>, v: &V, w: &V) {
+    if let Some(adjacent) = index.get_mut(v) {
+        adjacent.remove(w);
+        if adjacent.is_empty() {
+            index.remove(v);
+        }
+    }
+}
+
+impl<V, Tag, CL> DeltaCrdt for Graph<V, Tag, CL>
+where
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<GraphItem<V>, Tag, CL>;
+
+    fn bottom() -> Self {
+        Graph::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.join_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl<V, Tag, CL> Serialize for Graph<V, Tag, CL>
+    where
+        V: Key + Serialize,
+        Tag: TagT + Serialize,
+        CL: CausalLength + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            (&self.vertices, &self.edges).serialize(serializer)
+        }
+    }
+
+    impl<'de, V, Tag, CL> Deserialize<'de> for Graph<V, Tag, CL>
+    where
+        V: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let (vertices, edges) =
+                <(Set<V, Tag, CL>, Set<(V, V), Tag, CL>)>::deserialize(deserializer)?;
+            let present: Vec<(V, V)> = edges.iter().map(|(e, _)| e.clone()).collect();
+            let mut graph = Graph {
+                vertices,
+                edges,
+                successors: HashMap::new(),
+                predecessors: HashMap::new(),
+            };
+            for (from, to) in present {
+                graph.index_edge(from, to);
+            }
+            Ok(graph)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_edges_need_endpoints() {
+        let mut g: Graph<&str, u32, u16> = Graph::new();
+        g.add_vertex("a", 1);
+        g.add_vertex("b", 1);
+        g.add_vertex("c", 1);
+        assert_eq!(g.add_edge("a", "d", 2), None);
+        g.add_edge("a", "b", 2).unwrap();
+        g.add_edge("a", "c", 2).unwrap();
+        g.add_edge("c", "b", 2).unwrap();
+
+        let succ: HashSet<&str> = g.successors(&"a").copied().collect();
+        assert_eq!(succ, ["b", "c"].iter().copied().collect());
+        let pred: HashSet<&str> = g.predecessors(&"b").copied().collect();
+        assert_eq!(pred, ["a", "c"].iter().copied().collect());
+
+        assert_eq!(g.remove_vertex("c", 3).len(), 3);
+        assert_eq!(g.edges().count(), 1);
+        // adding the vertex again doesn't bring back its edges
+        g.add_vertex("c", 4);
+        assert_eq!(g.edges().count(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_remove_vertex() {
+        let mut g1: Graph<&str, u32, u16> = Graph::new();
+        let mut deltas = vec![g1.add_vertex("a", 1), g1.add_vertex("b", 1)];
+        let mut g2 = g1.clone();
+
+        deltas.extend(g1.add_edge("a", "b", 2));
+        deltas.extend(g2.remove_vertex("b", 2));
+        g1.merge(&g2, 0);
+        g2.merge(&g1, 0);
+        assert_eq!(g1, g2);
+        assert!(!g1.contains_edge(&"a", &"b"));
+        assert_eq!(g1.edges().count(), 0);
+        replay(&g1, deltas);
+
+        // the concurrently added edge comes back with its endpoint
+        g1.add_vertex("b", 3);
+        assert!(g1.contains_edge(&"a", &"b"));
+        assert!(g1.successors(&"a").eq([&"b"]));
+        assert!(g1.predecessors(&"b").eq([&"a"]));
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut g: Graph<u8, u32, u16> = Graph::new();
+        g.add_vertex(1, 1);
+        g.add_vertex(2, 1);
+        g.add_edge(1, 2, 2);
+
+        let data = serde_json::to_string(&g).unwrap();
+        let g2: Graph<u8, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(g, g2);
+        assert!(g2.successors(&1).eq([&2]));
+    }
+}
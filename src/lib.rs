@@ -2,22 +2,93 @@
 //! combined with an optional tag. The tag can be any type that satisfies `Ord + Copy`. A simple
 //! integer, wall clock, lamport timestamp, or even a hybrid logical clock from ["Logical Physical Clocks and Consistent Snapshots
 //! in Globally Distributed Databases"](http://www.cse.buffalo.edu/tech-reports/2014-04.pdf) may
-//! be used.
+//! be used. [LamportClock] and [HybridLogicalClock] are provided.
+
+// Lints newer than some of the tests, which keep their original forms
+#![cfg_attr(
+    test,
+    allow(
+        clippy::bool_assert_comparison,
+        clippy::needless_borrow,
+        clippy::needless_borrows_for_generic_args,
+        clippy::unnecessary_get_then_check,
+        clippy::useless_vec
+    )
+)]
 
 use num_integer::Integer;
 use num_traits::One;
 use std::hash::Hash;
 
+/// Batches of coalesced deltas
+pub mod batch;
+pub use self::batch::*;
+/// Delta buffer for acknowledgement-based delta propagation
+pub mod buffer;
+pub use self::buffer::*;
+/// Merge outcome reporting
+pub mod change;
+pub use self::change::*;
+/// Lamport and hybrid logical clocks for use as tags
+pub mod clock;
+pub use self::clock::*;
+/// Counters built from causal lengths
+pub mod counter;
+pub use self::counter::*;
+/// Hash tree digests for anti-entropy
+pub mod digest;
+pub use self::digest::*;
+/// JSON document built from causal length Map and Set
+#[cfg(feature = "json")]
+pub mod document;
+#[cfg(feature = "json")]
+pub use self::document::*;
+/// Enable-wins and disable-wins flags
+pub mod flag;
+pub use self::flag::*;
+/// Causal length Graph
+pub mod graph;
+pub use self::graph::*;
+/// Causal length List
+pub mod list;
+pub use self::list::*;
 /// Causal length Map
 pub mod map;
 pub use self::map::*;
+/// Causal length MultiMap
+pub mod multimap;
+pub use self::multimap::*;
+/// Causal length multi-value Register
+pub mod mvregister;
+pub use self::mvregister::*;
+/// Causal length Map with CRDT values
+pub mod nested;
+pub use self::nested::*;
+/// Change observers for Set and Map
+pub mod observer;
+pub use self::observer::*;
 /// Causal length Register
 pub mod register;
 pub use self::register::*;
+/// Replica wrapper which stamps tags from a clock
+pub mod replica;
+pub use self::replica::*;
 
 /// Causal length Set
 pub mod set;
 pub use self::set::*;
+/// Causal stability tracking for dropping tombstones
+pub mod stability;
+pub use self::stability::*;
+/// Storage backends for Set and Map
+pub mod storage;
+pub use self::storage::{Backend, Ordered, Storage};
+/// Causal length Text
+pub mod text;
+pub use self::text::*;
+/// Causal length Tree with move
+pub mod tree;
+pub use self::tree::*;
 
 /// CausalLength is abstracted to allow any of Rust's integer types to be used.
 pub trait CausalLength: Integer + One + Ord + Copy + Eq {}
@@ -36,6 +107,144 @@ pub trait TagT: Eq + Ord + Copy + Default {}
 impl<T> TagT for T where T: Eq + Ord + Copy + Default {}
 
 /// A CRDT type that replicates via delta registers.
+///
+/// Implemented by every CRDT in this crate, so replication code can be written once and used with
+/// any of them. The collections also provide inherent `merge` and `merge_register` methods that
+/// take a `min_tag` for ignoring old removes; [DeltaCrdt::join] and [DeltaCrdt::apply_delta] never
+/// ignore anything.
 pub trait DeltaCrdt {
+    /// Delta type, produced by mutating operations and accepted by [DeltaCrdt::apply_delta].
     type Delta;
+
+    /// Create the bottom (empty) state.
+    fn bottom() -> Self;
+
+    /// Merge the full state of another replica.
+    ///
+    /// Named apart from the collections' inherent `merge`, which also takes a `min_tag`.
+    fn join(&mut self, other: &Self);
+
+    /// Merge a single delta.
+    ///
+    /// Returns true if the delta made a visible difference, as reported by [Change::is_visible]:
+    /// something became present or absent, or a present value changed. Newer tags alone don't
+    /// count.
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool;
+
+    /// An iterator visiting deltas which together reproduce this state when applied to
+    /// [DeltaCrdt::bottom].
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_;
+}
+
+/// A CRDT whose deltas are addressed by key.
+///
+/// Merging is max-based, so any number of deltas for the same key can be coalesced into a single
+/// delta which has the same effect when merged.
+pub trait Coalesce: DeltaCrdt {
+    /// Key which deltas are addressed to
+    type DeltaKey: Key;
+
+    /// Returns the key a delta is addressed to.
+    fn delta_key(delta: &Self::Delta) -> Self::DeltaKey;
+
+    /// Coalesce `delta` into `into`, which must be addressed to the same key.
+    fn coalesce(into: &mut Self::Delta, delta: &Self::Delta);
+}
+
+/// A delta which carries tags.
+///
+/// Lets a [Replica] advance its clock past the tags of the deltas it merges.
+pub trait Tagged<Tag> {
+    /// Returns the greatest tag in the delta, or `None` if it carries none.
+    fn max_tag(&self) -> Option<Tag>;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use std::fmt::Debug;
+
+    /// Assert that `deltas`, merged out of order and redundantly into the bottom state, reproduce
+    /// `crdt`, and so do its own deltas and its full state.
+    pub(crate) fn replay<C>(crdt: &C, mut deltas: Vec<C::Delta>)
+    where
+        C: DeltaCrdt + Debug + PartialEq,
+        C::Delta: Clone,
+    {
+        deltas.extend(deltas.clone());
+        deltas.shuffle(&mut rand::rng());
+        let mut replayed = C::bottom();
+        for delta in deltas {
+            replayed.apply_delta(delta);
+        }
+        assert_eq!(&replayed, crdt);
+        replicate(crdt);
+    }
+
+    /// Like [replay], for CRDTs whose deltas depend on earlier ones: `deltas` are merged in order,
+    /// each one twice.
+    pub(crate) fn replay_in_order<C>(crdt: &C, deltas: Vec<C::Delta>)
+    where
+        C: DeltaCrdt + Debug + PartialEq,
+        C::Delta: Clone,
+    {
+        let mut replayed = C::bottom();
+        for delta in deltas {
+            replayed.apply_delta(delta.clone());
+            replayed.apply_delta(delta);
+        }
+        assert_eq!(&replayed, crdt);
+        replicate(crdt);
+    }
+
+    fn replicate<C>(crdt: &C)
+    where
+        C: DeltaCrdt + Debug + PartialEq,
+    {
+        let mut by_delta = C::bottom();
+        for delta in crdt.deltas() {
+            by_delta.apply_delta(delta);
+        }
+        assert_eq!(&by_delta, crdt);
+
+        let mut by_state = C::bottom();
+        by_state.join(crdt);
+        assert_eq!(&by_state, crdt);
+    }
+
+    #[test]
+    fn test_generic_replication() {
+        let mut s: Set<&str, u32, u16> = Set::new();
+        s.add("foo", 1);
+        s.add("bar", 1);
+        s.remove("foo", 2);
+        replicate(&s);
+
+        let mut m: Map<&str, u32, u32, u16> = Map::new();
+        m.insert("foo", 128, 1);
+        m.insert("bar", 256, 1);
+        m.remove("foo", 2);
+        replicate(&m);
+
+        let mut r: Register<&str, u32, u16> = Register::new("foo", 1);
+        r.set("bar", 2);
+        replicate(&r);
+    }
+
+    #[test]
+    fn test_visible_deltas() {
+        let mut s: Set<&str, u32, u16> = Set::new();
+        let add = s.clone().add("foo", 1);
+        assert!(s.apply_delta(add.clone()));
+        assert!(!s.apply_delta(add));
+        // a newer tag alone makes no visible difference
+        assert!(!s.apply_delta(Register::make("foo", 2, 1)));
+
+        let mut m: Map<&str, u32, u32, u16> = Map::new();
+        assert!(m.apply_delta(Register::make(("foo", 1), 1, 1)));
+        assert!(m.apply_delta(Register::make(("foo", 2), 2, 1)));
+        assert!(!m.apply_delta(Register::make(("foo", 2), 3, 1)));
+        assert!(m.apply_delta(Register::make(("foo", 2), 3, 2)));
+    }
 }
@@ -0,0 +1,431 @@
+use super::*;
+use crate::register::Register;
+use crate::set::OrderedSet;
+use std::cmp::min;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+// Largest gap left after an appended position, so appends don't exhaust a level too quickly.
+const BOUNDARY: u32 = 1 << 16;
+
+/// Dense position of a [List] element
+///
+/// A position can always be generated between any two others. Each digit carries the tag and
+/// replica id of the insert which created it, so concurrent inserts at the same index get distinct
+/// positions, even if their tags are equal.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Position<R, Tag>(Vec<(u32, Tag, R)>);
+
+impl<R, Tag> Position<R, Tag>
+where
+    R: TagT,
+    Tag: TagT,
+{
+    /// Generate a position after `lo` and before `hi` on behalf of `replica`, either of which may
+    /// be open.
+    ///
+    /// Positions from other replicas may leave no room below `hi`, if `hi` ends in a zero digit, or
+    /// isn't above `lo` at all. The position is then only after `lo`.
+    pub fn between(
+        lo: Option<&Position<R, Tag>>,
+        hi: Option<&Position<R, Tag>>,
+        replica: R,
+        tag: Tag,
+    ) -> Position<R, Tag> {
+        let lo = lo.map(|p| p.0.as_slice()).unwrap_or(&[]);
+        let mut hi = hi.map(|p| p.0.as_slice());
+        let mut digits = Vec::new();
+        for i in 0.. {
+            let l = lo
+                .get(i)
+                .copied()
+                .unwrap_or((0, Tag::default(), R::default()));
+            // past the end of `hi` there's no upper bound
+            let bound = hi.and_then(|hi| hi.get(i));
+            let (h, step) = match bound {
+                Some(h) => (h.0, u32::MAX),
+                None => (u32::MAX, BOUNDARY),
+            };
+            if h.saturating_sub(l.0) > 1 {
+                digits.push((l.0 + min((h - l.0) / 2, step), tag, replica));
+                break;
+            }
+            digits.push(l);
+            // below a digit smaller than `hi`'s, there's no upper bound
+            if bound.map_or(true, |h| l < *h) {
+                hi = None;
+            }
+        }
+        Position(digits)
+    }
+
+    // The `n`th position below this one, for runs of consecutive positions, with the same tag and
+    // replica as the last digit. Counts from one, as zero is kept free for `between`.
+    pub(crate) fn child(&self, n: u32) -> Position<R, Tag> {
+        let (tag, replica) = self
+            .0
+            .last()
+            .map_or((Tag::default(), R::default()), |d| (d.1, d.2));
+        let mut p = self.clone();
+        p.0.push((n, tag, replica));
+        p
+    }
+
+    // The position `n` places after this one, in a run of consecutive positions.
+    pub(crate) fn offset(&self, n: u32) -> Position<R, Tag> {
+        let mut p = self.clone();
+        if let Some(last) = p.0.last_mut() {
+            last.0 += n;
+        }
+        p
+    }
+
+    // How many places this position is after `start`, if both are in the same run.
+    pub(crate) fn run_offset(&self, start: &Position<R, Tag>) -> Option<u32> {
+        let (a, prefix_a) = self.0.split_last()?;
+        let (b, prefix_b) = start.0.split_last()?;
+        if prefix_a == prefix_b && (a.1, a.2) == (b.1, b.2) && a.0 >= b.0 {
+            Some(a.0 - b.0)
+        } else {
+            None
+        }
+    }
+}
+
+/// Causal Length List
+///
+/// An ordered list, where each element is a dense [Position] and an item, held in an
+/// [OrderedSet]. Presence of each element follows the causal length set, so removing and adding
+/// an element again behaves just like the paper's set. Concurrent inserts at the same index are
+/// ordered by tag, then by replica id, which is passed to each insert the same way as the tag.
+///
+/// Deltas are [Register]s over `(position, item)` pairs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct List<T, R, Tag, CL>
+where
+    T: Key + Ord,
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    elements: OrderedSet<(Position<R, Tag>, T), Tag, CL>,
+}
+
+impl<T, R, Tag, CL> List<T, R, Tag, CL>
+where
+    T: Key + Ord,
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    /// Create a new empty `List`
+    pub fn new() -> List<T, R, Tag, CL> {
+        List {
+            elements: OrderedSet::new(),
+        }
+    }
+
+    /// Returns the number of visible elements.
+    pub fn len(&self) -> usize {
+        self.elements.iter().count()
+    }
+
+    /// Returns true if the list has no visible elements.
+    pub fn is_empty(&self) -> bool {
+        self.elements.first().is_none()
+    }
+
+    /// Returns the item at `index`.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.element(index).map(|(_, item)| item)
+    }
+
+    /// Returns the position and item at `index`.
+    pub fn element(&self, index: usize) -> Option<&(Position<R, Tag>, T)> {
+        self.elements.iter().nth(index).map(|(e, _)| e)
+    }
+
+    /// An iterator visiting the items in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.elements.iter().map(|((_, item), _)| item)
+    }
+
+    /// Insert an item at `index` on behalf of `replica`, shifting all items after it.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(
+        &mut self,
+        index: usize,
+        item: T,
+        replica: R,
+        tag: Tag,
+    ) -> Register<(Position<R, Tag>, T), Tag, CL> {
+        let position = {
+            let mut visible = self
+                .elements
+                .iter()
+                .map(|((p, _), _)| p)
+                .skip(index.saturating_sub(1));
+            let (lo, hi) = if index == 0 {
+                (None, visible.next())
+            } else {
+                let lo = visible.next();
+                assert!(
+                    lo.is_some(),
+                    "insertion index (is {}) should be <= len",
+                    index
+                );
+                (lo, visible.next())
+            };
+            Position::between(lo, hi, replica, tag)
+        };
+        self.elements.add((position, item), tag)
+    }
+
+    /// Append an item to the end of the list on behalf of `replica`.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    pub fn push(
+        &mut self,
+        item: T,
+        replica: R,
+        tag: Tag,
+    ) -> Register<(Position<R, Tag>, T), Tag, CL> {
+        let lo = self.elements.last().map(|((p, _), _)| p);
+        let position = Position::between(lo, None, replica, tag);
+        self.elements.add((position, item), tag)
+    }
+
+    /// Remove the item at `index`, shifting all items after it.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize, tag: Tag) -> Option<<Self as DeltaCrdt>::Delta> {
+        let element = self.element(index)?.clone();
+        self.elements.remove(element, tag)
+    }
+
+    /// An iterator visiting all registers in position order.
+    pub fn register_iter(
+        &self,
+    ) -> impl Iterator<Item = Register<(Position<R, Tag>, T), Tag, CL>> + '_ {
+        self.elements.register_iter()
+    }
+
+    /// Merge a delta [Register] into a list.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    pub fn merge_register(
+        &mut self,
+        delta: Register<(Position<R, Tag>, T), Tag, CL>,
+        min_tag: Tag,
+    ) {
+        self.elements.merge_register(delta, min_tag);
+    }
+
+    /// Merge a delta [Register] into a list, see [List::merge_register].
+    ///
+    /// Returns the visible effect the delta had on the list.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<(Position<R, Tag>, T), Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<(Position<R, Tag>, T)> {
+        self.elements.merge_register_report(delta, min_tag)
+    }
+
+    /// Merge two lists.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        self.elements.merge(&other.elements, min_tag);
+    }
+
+    /// Filter out old remove tombstone deltas from the list.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.elements.retain(min_tag);
+    }
+}
+
+impl<T, R, Tag, CL> DeltaCrdt for List<T, R, Tag, CL>
+where
+    T: Key + Ord,
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    type Delta = Register<(Position<R, Tag>, T), Tag, CL>;
+
+    fn bottom() -> Self {
+        List::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.elements.apply_delta(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use quickcheck_macros::quickcheck;
+
+    fn items(list: &List<char, u8, u32, u16>) -> String {
+        list.iter().collect()
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut list: List<char, u8, u32, u16> = List::new();
+        list.insert(0, 'b', 1, 1);
+        list.insert(0, 'a', 1, 2);
+        list.push('d', 1, 3);
+        list.insert(2, 'c', 1, 4);
+        assert_eq!(items(&list), "abcd");
+        assert_eq!(list.get(2), Some(&'c'));
+        assert_eq!(list.len(), 4);
+
+        let delta = list.remove(1, 5).unwrap();
+        assert_eq!(items(&list), "acd");
+        assert_eq!(list.remove(3, 5), None);
+
+        // undelete by merging an add of the same element
+        let mut readd = delta.clone();
+        readd.length += 1;
+        list.merge_register(readd, 0);
+        assert_eq!(items(&list), "abcd");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_out_of_bounds() {
+        let mut list: List<char, u8, u32, u16> = List::new();
+        list.insert(1, 'a', 1, 1);
+    }
+
+    #[test]
+    fn test_dense_positions() {
+        let mut list: List<u32, u8, u32, u16> = List::new();
+        list.push(0, 1, 0);
+        list.push(1000, 1, 0);
+        // always insert in the same gap
+        for i in 1..1000 {
+            list.insert(i as usize, i, 1, i);
+        }
+        assert!(list.iter().copied().eq(0..=1000));
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let mut l1: List<char, u8, u32, u16> = List::new();
+        let mut deltas = vec![l1.push('a', 1, 1), l1.push('d', 1, 2)];
+        let mut l2 = l1.clone();
+
+        deltas.push(l1.insert(1, 'b', 1, 3));
+        deltas.push(l2.insert(1, 'c', 2, 4));
+        deltas.extend(l2.remove(0, 4));
+        l1.merge(&l2, 0);
+        l2.merge(&l1, 0);
+        assert_eq!(l1, l2);
+        assert_eq!(items(&l1), "bcd");
+        replay(&l1, deltas);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut list: List<char, u8, u32, u16> = List::new();
+        list.push('a', 1, 1);
+        list.push('b', 1, 2);
+        list.remove(0, 3);
+
+        let data = serde_json::to_string(&list).unwrap();
+        let list2: List<char, u8, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(list, list2);
+    }
+
+    #[test]
+    fn test_same_tag_inserts() {
+        let mut l1: List<char, u8, u32, u16> = List::new();
+        l1.push('a', 1, 1);
+        let mut l2 = l1.clone();
+
+        // concurrent inserts in the same gap with equal tags keep both items
+        l1.insert(1, 'b', 1, 2);
+        l2.insert(1, 'b', 2, 2);
+        l1.merge(&l2, 0);
+        assert_eq!(items(&l1), "abb");
+    }
+
+    #[test]
+    fn test_insert_after_remote_positions() {
+        let mut list: List<char, u8, u32, u16> = List::new();
+        list.push('a', 1, 1);
+        let a = list.register_iter().next().unwrap().item.0;
+        // a remote position ending in a zero digit, and another equal to it
+        let mut zero = a.clone();
+        zero.0.push((0, 0, 0));
+        list.merge_register(Register::new((zero.clone(), 'b'), 2), 0);
+        list.merge_register(Register::new((zero, 'c'), 2), 0);
+        assert_eq!(items(&list), "abc");
+        for i in 0..=3 {
+            list.insert(i, 'x', 1, 3);
+        }
+        assert_eq!(list.len(), 7);
+    }
+
+    type Digits = Vec<(u32, u8, u8)>;
+
+    // positions are never generated ending in an all-zero digit, nothing fits below one
+    fn check_between(lo: Digits, hi: Digits, replica: u8, tag: u8) -> bool {
+        let (lo, hi) = (Position(lo), Position(hi));
+        if lo >= hi || hi.0.last() == Some(&(0, 0, 0)) {
+            return true;
+        }
+        let p = Position::between(Some(&lo), Some(&hi), replica, tag);
+        lo < p && p < hi
+    }
+
+    // small digits, so that zero and adjacent digits are common
+    fn dense(digits: Digits) -> Digits {
+        digits
+            .into_iter()
+            .map(|(n, t, r)| (n % 3, t % 2, r % 2))
+            .collect()
+    }
+
+    #[quickcheck]
+    fn test_between(lo: Digits, hi: Digits, replica: u8, tag: u8) -> bool {
+        check_between(lo, hi, replica, tag)
+    }
+
+    // positions from other replicas may leave no room, but never make `between` fail
+    #[quickcheck]
+    fn test_between_unchecked(lo: Digits, hi: Digits, replica: u8, tag: u8) -> bool {
+        let (lo, hi) = (Position(dense(lo)), Position(dense(hi)));
+        Position::between(Some(&lo), Some(&hi), replica, tag) > lo
+    }
+
+    #[quickcheck]
+    fn test_between_dense(lo: Digits, hi: Digits, replica: u8, tag: u8) -> bool {
+        check_between(dense(lo), dense(hi), replica % 2, tag % 2)
+    }
+}
@@ -1,8 +1,12 @@
 use super::*;
 use crate::register::Register;
+use crate::storage::{Backend, Storage, TagIndex};
 use std::borrow::Borrow;
 use std::cmp::max;
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::ops::RangeBounds;
 
 /// Causal Length Map
 ///
@@ -10,31 +14,91 @@ use std::collections::HashMap;
 ///
 /// `Map` uses the tag for garbage collection of old removed members, and to
 /// resolve conflicting values for the same key and causal length.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct Map<K, V, Tag, CL>
+///
+/// Entries are stored in a `HashMap` by default. Use [OrderedMap] for a map which visits entries
+/// in key order, and supports range queries.
+#[derive(Clone, Debug)]
+pub struct Map<K, V, Tag, CL, S = RandomState>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+    map: S::Store<Register<V, Tag, CL>>,
+    index: TagIndex<K, Tag, S, S::Store<()>>,
+}
+
+/// Causal Length Map which visits entries in key order
+pub type OrderedMap<K, V, Tag, CL> = Map<K, V, Tag, CL, Ordered>;
+
+impl<K, V, Tag, CL, S> PartialEq for Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self.map.iter().all(|(k, v)| other.map.get(k) == Some(v))
+    }
+}
+
+impl<K, V, Tag, CL, S> Eq for Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+}
+
+impl<K, V, Tag, CL, S> Default for Map<K, V, Tag, CL, S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<K> + Default,
 {
-    map: HashMap<K, Register<V, Tag, CL>>,
+    fn default() -> Self {
+        Map::new()
+    }
 }
 
-impl<K, V, Tag, CL> Map<K, V, Tag, CL>
+impl<K, V, Tag, CL, S> Map<K, V, Tag, CL, S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<K>,
 {
     /// Create an empty `Map`
-    pub fn new() -> Map<K, V, Tag, CL> {
+    pub fn new() -> Map<K, V, Tag, CL, S>
+    where
+        S: Default,
+    {
+        Map::with_backend(S::default())
+    }
+
+    /// Create an empty `Map` which stores its entries using `backend`.
+    pub fn with_backend(backend: S) -> Map<K, V, Tag, CL, S> {
         Map {
-            map: HashMap::new(),
+            map: backend.store(),
+            index: TagIndex::of(backend, std::iter::empty()),
         }
     }
 
+    /// Returns the backend storing the map's entries.
+    pub fn backend(&self) -> S {
+        S::of(&self.map)
+    }
+
     /// Returns a reference to the value and tag corresponding to the key.
     pub fn get<Q>(&self, key: Q) -> Option<(&V, Tag)>
     where
@@ -58,63 +122,52 @@ where
 
     /// Inserts a key, value, and tag into the map.
     ///
-    /// If the map did not have this key present, [`None`] is returned.
-    ///
-    /// If the map did have this key present, the value is updated, and the old
-    /// value is returned, along with the old tag.
-    pub fn insert(&mut self, key: K, value: V, tag: Tag) -> Option<(V, Tag)> {
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    pub fn insert(&mut self, key: K, value: V, tag: Tag) -> Register<(K, V), Tag, CL> {
         let one: CL = CL::one();
-        let e = self.map.entry(key);
-        match e {
-            std::collections::hash_map::Entry::Occupied(mut oe) => {
-                let oe = oe.get_mut();
+        let mut old = None;
+        let oe = self
+            .map
+            .upsert(key.clone(), Register::make(value, tag, one), |oe, new| {
                 // s{e |-> s(e)+1} if even
-                //s if odd s(e)
+                //s if odd s(e)
                 if oe.length.is_even() {
                     oe.length = oe.length + one;
-                } else if oe.item != value {
+                } else if oe.item != new.item {
                     // Special adaptation for a map: we add two to the causal length
                     // in cases where the key exists, but the value is not the same.
                     // This is equivalent to removing and re-adding the key.
                     oe.length = oe.length + one + one;
                 }
                 // always use the max value of tag
+                old = Some(oe.tag);
                 oe.tag = max(oe.tag, tag);
-                let r = oe.item.clone();
-                oe.item = value;
-                Some((r, oe.tag))
-            }
-            _ => {
-                e.or_insert_with(|| Register::make(value, tag, one));
-                None
-            }
-        }
+                oe.item = new.item;
+            });
+        self.index.update(&key, old, Some(oe.tag));
+        Register::make((key, oe.item.clone()), oe.tag, oe.length)
     }
 
-    /// Remove a key from the map, returning the stored value and tag if
-    /// the key was in the map.
-    pub fn remove(&mut self, key: K, tag: Tag) -> Option<(V, Tag)> {
-        let e = self.map.entry(key);
-        match e {
-            std::collections::hash_map::Entry::Occupied(mut oe) => {
-                let oe = oe.get_mut();
-                oe.tag = max(oe.tag, tag);
-
-                // {} if even(s(e))
-                // { e |-> s(e) + 1 } if odd(s(e))
-                if oe.length.is_odd() {
-                    oe.length = oe.length + CL::one();
-                    Some((oe.item.clone(), oe.tag))
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
+    /// Remove a key from the map.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `key` isn't in the map.
+    pub fn remove(&mut self, key: K, tag: Tag) -> Option<Register<(K, V), Tag, CL>> {
         // ignore attempts to remove items that aren't present...
+        let oe = self.map.get_mut(&key)?;
+        // {} if even(s(e))
+        // { e |-> s(e) + 1 } if odd(s(e))
+        if oe.length.is_even() {
+            return None;
+        }
+        oe.length = oe.length + CL::one();
+        let old = oe.tag;
+        oe.tag = max(oe.tag, tag);
+        self.index.update(&key, Some(old), Some(oe.tag));
+        Some(Register::make((key, oe.item.clone()), oe.tag, oe.length))
     }
 
-    /// An iterator visiting all key, value, tag tuples in arbitrary order.
+    /// An iterator visiting all key, value, tag tuples in storage order.
     pub fn iter(&self) -> impl Iterator<Item = (K, V, Tag)> + '_ {
         self.map
             .iter()
@@ -122,8 +175,8 @@ where
             .map(|(k, v)| (k.clone(), v.item.clone(), v.tag))
     }
 
-    /// An iterator visiting all delta registers in arbitrary order.
-    pub fn register_iter(&self) -> impl Iterator<Item = <Self as DeltaCrdt>::Delta> + '_ {
+    /// An iterator visiting all delta registers in storage order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<(K, V), Tag, CL>> + '_ {
         self.map
             .iter()
             .map(|(k, v)| Register::make((k.clone(), v.item.clone()), v.tag, v.length))
@@ -132,22 +185,47 @@ where
     /// Merge a delta [Register] into a map.
     ///
     /// Remove deltas with a tag value less than `min_tag` will be ignored.
-    pub fn merge_register(&mut self, delta: <Self as DeltaCrdt>::Delta, min_tag: Tag) {
+    pub fn merge_register(&mut self, delta: Register<(K, V), Tag, CL>, min_tag: Tag) {
         if delta.length.is_even() && delta.tag < min_tag {
             // ignore excessively old remove records
             return;
         }
+        self.join_register(delta);
+    }
 
-        match self.map.entry(delta.item.0.clone()) {
-            Entry::Occupied(mut e) => {
-                let e = e.get_mut();
+    // Returns true if the key became present or absent, or its value changed.
+    fn join_register(&mut self, delta: Register<(K, V), Tag, CL>) -> bool {
+        let (key, value) = delta.item;
+        let reg = Register::make(value, delta.tag, delta.length);
+        let mut old = None;
+        let mut visible = reg.length.is_odd();
+        let e = self.map.upsert(key.clone(), reg, |e, reg| {
+            old = Some(e.tag);
+            visible = e.merge_visible(&reg);
+        });
+        self.index.update(&key, old, Some(e.tag));
+        visible
+    }
 
-                let reg = Register::make(delta.item.1.clone(), delta.tag, delta.length);
-                e.merge(&reg);
-            }
-            Entry::Vacant(e) => {
-                e.insert(Register::make(delta.item.1, delta.tag, delta.length));
-            }
+    /// Merge a delta [Register] into a map, see [Map::merge_register].
+    ///
+    /// Returns the visible effect the delta had on the map.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<(K, V), Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<K, V> {
+        if delta.length.is_even() && delta.tag < min_tag {
+            return Change::Ignored(delta.item.0);
+        }
+        let key = delta.item.0.clone();
+        let before = self.get(&key).map(|(v, _)| v.clone());
+        self.merge_register(delta, min_tag);
+        match (before, self.get(&key).map(|(v, _)| v.clone())) {
+            (None, Some(new)) => Change::Added(key, new),
+            (Some(old), None) => Change::Removed(key, old),
+            (Some(old), Some(new)) if old != new => Change::Updated { key, old, new },
+            _ => Change::Unchanged(key),
         }
     }
 
@@ -160,30 +238,185 @@ where
         }
     }
 
+    /// Merge two maps, reporting every key that became visible, hidden, or changed value, or whose
+    /// remove was ignored.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge_report(&mut self, other: &Self, min_tag: Tag) -> Vec<Change<K, V>> {
+        other
+            .register_iter()
+            .map(|delta| self.merge_register_report(delta, min_tag))
+            .filter(|change| !matches!(change, Change::Unchanged(_)))
+            .collect()
+    }
+
+    /// Drop the index of keys by tag, so [Map::registers_since] scans every register.
+    ///
+    /// The index costs an entry per key, stored with the map's backend. Maps keep it from creation,
+    /// and rebuild it when deserialized.
+    pub fn disable_tag_index(&mut self) {
+        self.index = TagIndex::new();
+    }
+
+    /// Rebuild the index of keys by tag, after [Map::disable_tag_index].
+    pub fn enable_tag_index(&mut self) {
+        self.index = TagIndex::of(self.backend(), self.map.iter().map(|(k, v)| (k, v.tag)));
+    }
+
+    /// An iterator visiting all registers with a tag greater than `tag`, in tag order, using the tag
+    /// index; an O(n) scan in storage order if [Map::disable_tag_index] was called.
+    ///
+    /// Registers are found by their current tag, so a register merged from another replica after
+    /// `tag` was seen, but tagged at or before it, is missed. This only finds every change since
+    /// `tag` if registers are merged in tag order; otherwise use [Map::delta_for] or a [Digest].
+    pub fn registers_since(
+        &self,
+        tag: Tag,
+    ) -> impl Iterator<Item = Register<(K, V), Tag, CL>> + '_ {
+        let indexed = self.index.since(tag).map(|keys| {
+            keys.filter_map(move |k| {
+                self.map
+                    .get(k)
+                    .map(|v| Register::make((k.clone(), v.item.clone()), v.tag, v.length))
+            })
+        });
+        let scanned = match indexed {
+            Some(_) => None,
+            None => Some(self.register_iter().filter(move |r| r.tag > tag)),
+        };
+        indexed
+            .into_iter()
+            .flatten()
+            .chain(scanned.into_iter().flatten())
+    }
+
+    /// An iterator visiting the registers which would change `other` when merged into it, in
+    /// storage order.
+    ///
+    /// Those are the keys `other` doesn't have, or has with a lower causal length, tag, or value.
+    pub fn delta_for<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> impl Iterator<Item = Register<(K, V), Tag, CL>> + 'a {
+        self.register_iter()
+            .filter(move |delta| match other.map.get(&delta.item.0) {
+                Some(e) => (delta.length, delta.tag, &delta.item.1) > (e.length, e.tag, &e.item),
+                None => true,
+            })
+    }
+
     /// Filter out old remove tombstone deltas from the map.
     ///
     /// Remove deltas with a tag value less than `min_tag` will be removed.
     pub fn retain(&mut self, min_tag: Tag) {
+        let index = &mut self.index;
+        self.map.retain(|k, v| {
+            let keep = v.length.is_odd() || min_tag <= v.tag;
+            if !keep {
+                index.update(k, Some(v.tag), None);
+            }
+            keep
+        });
+    }
+}
+
+impl<K, V, Tag, CL, S> Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: BuildHasher + Clone,
+{
+    /// Create an empty `Map` which will use the given hasher to hash keys.
+    pub fn with_hasher(hasher: S) -> Map<K, V, Tag, CL, S> {
+        Map {
+            map: HashMap::with_hasher(hasher.clone()),
+            index: TagIndex::of(hasher, std::iter::empty()),
+        }
+    }
+
+    /// Create an empty `Map` with space for at least `capacity` keys, which will use the given
+    /// hasher to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Map<K, V, Tag, CL, S> {
+        Map {
+            map: HashMap::with_capacity_and_hasher(capacity, hasher.clone()),
+            index: TagIndex::of(hasher, std::iter::empty()),
+        }
+    }
+
+    /// Returns a reference to the map's hasher.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+}
+
+impl<K, V, Tag, CL> Map<K, V, Tag, CL, Ordered>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// An iterator visiting the key, value, tag tuples within `range`, in key order.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (K, V, Tag)> + '_
+    where
+        R: RangeBounds<K>,
+    {
         self.map
-            .retain(|_k, v| v.length.is_odd() || min_tag < v.tag);
+            .range(range)
+            .filter(|(_k, v)| v.length.is_odd())
+            .map(|(k, v)| (k.clone(), v.item.clone(), v.tag))
+    }
+
+    /// An iterator visiting the delta registers within `range`, in key order.
+    pub fn register_range<R>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Register<(K, V), Tag, CL>> + '_
+    where
+        R: RangeBounds<K>,
+    {
+        self.map
+            .range(range)
+            .map(|(k, v)| Register::make((k.clone(), v.item.clone()), v.tag, v.length))
+    }
+
+    /// Returns the first entry in the map.
+    pub fn first(&self) -> Option<(&K, &V, Tag)> {
+        Self::visible(&self.map).next()
+    }
+
+    /// Returns the last entry in the map.
+    pub fn last(&self) -> Option<(&K, &V, Tag)> {
+        Self::visible(&self.map).next_back()
+    }
+
+    fn visible(
+        map: &BTreeMap<K, Register<V, Tag, CL>>,
+    ) -> impl DoubleEndedIterator<Item = (&K, &V, Tag)> + '_ {
+        map.iter()
+            .filter(|(_k, v)| v.length.is_odd())
+            .map(|(k, v)| (k, &v.item, v.tag))
     }
 }
 
 #[cfg(feature = "serialization")]
 mod serialization {
     use super::*;
-    use serde::de::{SeqAccess, Visitor};
+    use serde::de::{DeserializeSeed, SeqAccess, Visitor};
     use serde::ser::SerializeSeq;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use std::fmt::Formatter;
     use std::marker::PhantomData;
 
-    impl<K, V, Tag, CL> Serialize for Map<K, V, Tag, CL>
+    impl<K, V, Tag, CL, B> Serialize for Map<K, V, Tag, CL, B>
     where
         K: Key + Ord + Serialize,
         V: Value + Hash + Ord + Serialize,
         Tag: TagT + Serialize,
         CL: CausalLength + Serialize,
+        B: Backend<K>,
     {
         fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
         where
@@ -197,21 +430,37 @@ mod serialization {
         }
     }
 
-    struct DeltaVisitor<K, V, Tag, CL>(
-        PhantomData<K>,
-        PhantomData<V>,
-        PhantomData<Tag>,
-        PhantomData<CL>,
-    );
+    /// Deserializes a [Map] whose entries are stored using `backend`.
+    ///
+    /// The `Deserialize` impl creates the backend with `Default`. Use this seed instead to carry a
+    /// seeded hasher, or one without `Default`, through deserialization: hashers aren't
+    /// serialized along with the map.
+    pub struct MapSeed<K, V, Tag, CL, B> {
+        backend: B,
+        marker: PhantomData<(K, V, Tag, CL)>,
+    }
+
+    impl<K, V, Tag, CL, B> MapSeed<K, V, Tag, CL, B> {
+        /// Create a seed which stores the deserialized entries using `backend`.
+        pub fn new(backend: B) -> MapSeed<K, V, Tag, CL, B> {
+            MapSeed {
+                backend,
+                marker: PhantomData,
+            }
+        }
+    }
+
+    struct DeltaVisitor<K, V, Tag, CL, B>(B, PhantomData<(K, V, Tag, CL)>);
 
-    impl<'de, K, V, Tag, CL> Visitor<'de> for DeltaVisitor<K, V, Tag, CL>
+    impl<'de, K, V, Tag, CL, B> Visitor<'de> for DeltaVisitor<K, V, Tag, CL, B>
     where
         K: Key + Ord + Deserialize<'de>,
         V: Value + Hash + Ord + Deserialize<'de>,
         Tag: TagT + Deserialize<'de>,
         CL: CausalLength + Deserialize<'de>,
+        B: Backend<K>,
     {
-        type Value = HashMap<K, Register<V, Tag, CL>>;
+        type Value = B::Store<Register<V, Tag, CL>>;
 
         fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
             formatter.write_str("a tuple of key, value, tag, and causal length")
@@ -221,8 +470,7 @@ mod serialization {
         where
             A: SeqAccess<'de>,
         {
-            let mut map: HashMap<K, Register<V, Tag, CL>> =
-                HashMap::with_capacity(seq.size_hint().unwrap_or(0));
+            let mut map = self.0.store_with_capacity(seq.size_hint().unwrap_or(0));
             while let Some(d) = seq.next_element::<(K, V, Tag, CL)>()? {
                 map.insert(d.0, Register::make(d.1, d.2, d.3));
             }
@@ -230,45 +478,102 @@ mod serialization {
         }
     }
 
-    impl<'de, K, V, Tag, CL> Deserialize<'de> for Map<K, V, Tag, CL>
+    impl<'de, K, V, Tag, CL, B> DeserializeSeed<'de> for MapSeed<K, V, Tag, CL, B>
     where
         K: Key + Ord + Deserialize<'de>,
         V: Value + Hash + Ord + Deserialize<'de>,
         Tag: TagT + Deserialize<'de>,
         CL: CausalLength + Deserialize<'de>,
+        B: Backend<K>,
     {
-        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        type Value = Map<K, V, Tag, CL, B>;
+
+        fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
         where
             D: Deserializer<'de>,
         {
-            let visitor =
-                DeltaVisitor::<K, V, Tag, CL>(PhantomData, PhantomData, PhantomData, PhantomData);
-            let map = deserializer.deserialize_seq(visitor)?;
+            let map = deserializer.deserialize_seq(DeltaVisitor(self.backend, PhantomData))?;
+            let index = TagIndex::of(B::of(&map), map.iter().map(|(k, v)| (k, v.tag)));
+            Ok(Map { map, index })
+        }
+    }
 
-            Ok(Map { map })
+    impl<'de, K, V, Tag, CL, B> Deserialize<'de> for Map<K, V, Tag, CL, B>
+    where
+        K: Key + Ord + Deserialize<'de>,
+        V: Value + Hash + Ord + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+        B: Backend<K> + Default,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            MapSeed::new(B::default()).deserialize(deserializer)
         }
     }
 }
 
-impl<K, V, Tag, CL> DeltaCrdt for Map<K, V, Tag, CL>
+#[cfg(feature = "serialization")]
+pub use serialization::*;
+
+impl<K, V, Tag, CL, S> DeltaCrdt for Map<K, V, Tag, CL, S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<K> + Default,
 {
     type Delta = Register<(K, V), Tag, CL>;
+
+    fn bottom() -> Self {
+        Map::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.join_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
 }
 
-impl<K, V, Tag, CL> From<Set<(K, V), Tag, CL>> for Map<K, V, Tag, CL>
+impl<K, V, Tag, CL, S> Coalesce for Map<K, V, Tag, CL, S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<K> + Default,
 {
-    fn from(s: Set<(K, V), Tag, CL>) -> Self {
-        let mut m = Self::new();
+    type DeltaKey = K;
+
+    fn delta_key(delta: &Self::Delta) -> K {
+        delta.item.0.clone()
+    }
+
+    fn coalesce(into: &mut Self::Delta, delta: &Self::Delta) {
+        into.merge(delta);
+    }
+}
+
+impl<K, V, Tag, CL, S> From<Set<(K, V), Tag, CL, S>> for Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K> + Backend<(K, V)>,
+{
+    fn from(s: Set<(K, V), Tag, CL, S>) -> Self {
+        let mut m = Map::with_backend(s.backend());
         for item in s.register_iter() {
             m.merge_register(item, Tag::default());
         }
@@ -276,15 +581,16 @@ where
     }
 }
 
-impl<K, V, Tag, CL> From<Map<K, V, Tag, CL>> for Set<(K, V), Tag, CL>
+impl<K, V, Tag, CL, S> From<Map<K, V, Tag, CL, S>> for Set<(K, V), Tag, CL, S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<K> + Backend<(K, V)>,
 {
-    fn from(m: Map<K, V, Tag, CL>) -> Self {
-        let mut s = Self::new();
+    fn from(m: Map<K, V, Tag, CL, S>) -> Self {
+        let mut s = Set::with_backend(m.backend());
         for item in m.register_iter() {
             s.merge_register(item, Tag::default());
         }
@@ -292,15 +598,16 @@ where
     }
 }
 
-impl<K, V, Tag, CL> From<Map<K, V, Tag, CL>> for HashMap<K, (V, Tag)>
+impl<K, V, Tag, CL, S> From<Map<K, V, Tag, CL, S>> for HashMap<K, (V, Tag), S>
 where
     K: Key + Ord,
     V: Value + Hash + Eq + Ord,
     Tag: TagT,
     CL: CausalLength,
+    S: BuildHasher + Clone,
 {
-    fn from(m: Map<K, V, Tag, CL>) -> Self {
-        let mut h = Self::new();
+    fn from(m: Map<K, V, Tag, CL, S>) -> Self {
+        let mut h = Self::with_capacity_and_hasher(m.map.len(), m.hasher().clone());
         for item in m.register_iter() {
             if let Some(((k, v), tag)) = item.get() {
                 h.insert(k.clone(), (v.clone(), tag));
@@ -310,9 +617,23 @@ where
     }
 }
 
-#[cfg(feature = "serialization")]
-pub use serialization::*;
-use std::collections::hash_map::Entry;
+impl<K, V, Tag, CL> From<Map<K, V, Tag, CL, Ordered>> for BTreeMap<K, (V, Tag)>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn from(m: Map<K, V, Tag, CL, Ordered>) -> Self {
+        let mut b = Self::new();
+        for item in m.register_iter() {
+            if let Some(((k, v), tag)) = item.get() {
+                b.insert(k.clone(), (v.clone(), tag));
+            }
+        }
+        b
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -506,6 +827,170 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_deltas() {
+        let mut m1: Map<&str, u32, u32, u16> = Map::new();
+        let mut m2: Map<&str, u32, u32, u16> = Map::new();
+
+        let mut deltas = vec![
+            m1.insert("foo", 128, 1),
+            m1.insert("bar", 256, 1),
+            m1.remove("foo", 2).unwrap(),
+            m1.insert("bar", 512, 3),
+        ];
+        assert_eq!(m1.remove("baz", 4), None);
+        deltas.push(m1.remove("bar", 4).unwrap());
+        // already removed
+        assert_eq!(m1.remove("bar", 5), None);
+        for delta in deltas {
+            m2.merge_register(delta, 0);
+        }
+        assert_eq!(m1, m2);
+    }
+
+    #[test]
+    fn test_merge_report() {
+        let mut m1: Map<&str, u32, u32, u16> = Map::new();
+        let mut m2: Map<&str, u32, u32, u16> = Map::new();
+
+        m1.insert("foo", 128, 1);
+        m1.insert("bar", 256, 1);
+        m1.insert("qux", 1024, 1);
+        m2.merge(&m1, 0);
+        m1.remove("foo", 2);
+        m1.insert("bar", 512, 2);
+        m1.insert("baz", 64, 2);
+        m1.insert("old", 32, 0);
+        m1.remove("old", 0);
+
+        let mut changes = m2.merge_report(&m1, 1);
+        changes.sort_by_key(|c| *c.key());
+        assert_eq!(
+            changes,
+            vec![
+                Change::Updated {
+                    key: "bar",
+                    old: 256,
+                    new: 512
+                },
+                Change::Added("baz", 64),
+                Change::Removed("foo", 128),
+                Change::Ignored("old"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registers_since() {
+        let mut m: Map<&str, u32, u32, u16> = Map::new();
+        m.insert("foo", 128, 1);
+        m.insert("bar", 256, 2);
+        m.insert("foo", 512, 3);
+        m.remove("bar", 4);
+        // a longer causal length wins, even with an older tag
+        m.merge_register(Register::make(("foo", 1024), 0, 5), 0);
+        let since: Vec<_> = m.registers_since(1).map(|r| r.item).collect();
+        assert_eq!(since, vec![("bar", 256)]);
+
+        m.retain(5);
+        assert_eq!(m.registers_since(1).count(), 0);
+    }
+
+    #[test]
+    fn test_delta_for() {
+        let mut m1: Map<&str, u32, u32, u16> = Map::new();
+        m1.insert("foo", 128, 1);
+        m1.insert("bar", 256, 1);
+        let mut m2 = m1.clone();
+        assert_eq!(m1.delta_for(&m2).count(), 0);
+
+        m1.remove("foo", 2);
+        m1.insert("bar", 512, 2);
+        m2.insert("baz", 64, 2);
+        let mut deltas: Vec<_> = m1.delta_for(&m2).map(|r| *r.item()).collect();
+        deltas.sort_unstable();
+        assert_eq!(deltas, vec![("bar", 512), ("foo", 128)]);
+
+        // a concurrent value for the same key only changes the replica it wins on
+        let mut m3 = m2.clone();
+        m2.insert("baz", 32, 2);
+        m3.insert("baz", 16, 2);
+        assert_eq!(m2.delta_for(&m3).count(), 1);
+        assert_eq!(m3.delta_for(&m2).count(), 0);
+
+        for delta in m1.delta_for(&m2).collect::<Vec<_>>() {
+            m2.merge_register(delta, 0);
+        }
+        assert_eq!(m1.delta_for(&m2).count(), 0);
+    }
+
+    #[test]
+    fn test_ordered() {
+        let mut m: OrderedMap<&str, u32, u32, u16> = OrderedMap::new();
+
+        m.insert("delta", 4, 1);
+        m.insert("alpha", 1, 1);
+        m.insert("charlie", 3, 1);
+        m.insert("bravo", 2, 1);
+        m.insert("echo", 5, 1);
+        m.remove("alpha", 2);
+
+        let keys: Vec<&str> = m.iter().map(|(k, _, _)| k).collect();
+        assert_eq!(keys, vec!["bravo", "charlie", "delta", "echo"]);
+        let keys: Vec<&str> = m.range("b".."d").map(|(k, _, _)| k).collect();
+        assert_eq!(keys, vec!["bravo", "charlie"]);
+        let keys: Vec<&str> = m.register_range(.."b").map(|r| r.item.0).collect();
+        assert_eq!(keys, vec!["alpha"]);
+        assert_eq!(m.first(), Some((&"bravo", &2, 1)));
+        assert_eq!(m.last(), Some((&"echo", &5, 1)));
+    }
+
+    #[test]
+    fn test_hasher() {
+        type Hasher = std::hash::BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+        let mut m: Map<u32, u32, u32, u16, Hasher> =
+            Map::with_capacity_and_hasher(8, Hasher::default());
+        m.insert(1, 10, 1);
+        m.insert(2, 20, 1);
+        m.remove(2, 2);
+
+        let s: Set<(u32, u32), u32, u16, Hasher> = m.clone().into();
+        let m2: Map<u32, u32, u32, u16, Hasher> = s.into();
+        assert_eq!(m, m2);
+        let h: HashMap<u32, (u32, u32), Hasher> = m2.into();
+        assert_eq!(h.len(), 1);
+
+        let mut o: OrderedMap<u32, u32, u32, u16> = OrderedMap::new();
+        o.insert(2, 20, 1);
+        o.insert(1, 10, 1);
+        let b: BTreeMap<u32, (u32, u32)> = o.into();
+        assert_eq!(
+            b.into_iter().collect::<Vec<_>>(),
+            vec![(1, (10, 1)), (2, (20, 1))]
+        );
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_deserialize_seed() {
+        use serde::de::DeserializeSeed;
+        use std::hash::BuildHasher;
+
+        let hasher = RandomState::new();
+        let mut m: Map<u32, u32, u32, u16> = Map::with_hasher(hasher.clone());
+        m.insert(1, 10, 1);
+        m.insert(2, 20, 1);
+
+        let data = serde_json::to_string(&m).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&data);
+        let m2 = MapSeed::new(hasher.clone())
+            .deserialize(&mut deserializer)
+            .unwrap();
+        // the hasher is carried through rather than replaced by a fresh one
+        assert_eq!(m2.hasher().hash_one(1), hasher.hash_one(1));
+        assert_eq!(m, m2);
+    }
+
     #[cfg(feature = "serialization")]
     #[test]
     fn test_serialization() {
@@ -590,6 +1075,27 @@ mod tests {
         left == right
     }
 
+    #[quickcheck]
+    fn is_tag_index_consistent(
+        xs: Vec<Register<(u8, u8), u8, u8>>,
+        ys: Vec<Register<(u8, u8), u8, u8>>,
+        min_tag: u8,
+        since: u8,
+    ) -> bool {
+        let mut map = Map::default();
+        map.disable_tag_index();
+        map.enable_tag_index();
+        let mut map = xs.iter().fold(map, merge);
+        map.retain(min_tag);
+        let mut map = ys.iter().fold(map, merge);
+        map.retain(min_tag / 2);
+        let mut indexed: Vec<_> = map.registers_since(since).collect();
+        let mut scanned: Vec<_> = map.register_iter().filter(|r| r.tag > since).collect();
+        indexed.sort_by_key(|r| r.item);
+        scanned.sort_by_key(|r| r.item);
+        indexed == scanned
+    }
+
     mod simple_model {
         use super::*;
         use quickcheck::{Arbitrary, Gen};
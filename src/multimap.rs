@@ -0,0 +1,369 @@
+use super::*;
+use crate::register::Register;
+use crate::set::Set;
+use std::collections::HashMap;
+
+/// Causal Length MultiMap
+///
+/// A map from each key to an independent causal length [Set] of values, so concurrent adds of
+/// different values for the same key all survive. Deltas are [Register]s over `(key, value)`
+/// pairs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MultiMap<K, V, Tag, CL>
+where
+    K: Key,
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    map: HashMap<K, Set<V, Tag, CL>>,
+}
+
+impl<K, V, Tag, CL> MultiMap<K, V, Tag, CL>
+where
+    K: Key,
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new empty `MultiMap`
+    pub fn new() -> MultiMap<K, V, Tag, CL> {
+        MultiMap {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Returns an iterator over the values visible for `key`, with their tags.
+    pub fn get_all(&self, key: &K) -> impl Iterator<Item = (&V, Tag)> + '_ {
+        self.map.get(key).into_iter().flat_map(|set| set.iter())
+    }
+
+    /// Returns true if `value` is visible for `key`.
+    pub fn contains(&self, key: &K, value: &V) -> bool {
+        self.map.get(key).is_some_and(|set| set.contains(value))
+    }
+
+    /// Returns true if any value is visible for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get_all(key).next().is_some()
+    }
+
+    /// Add a value for a key.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    pub fn insert(&mut self, key: K, value: V, tag: Tag) -> Register<(K, V), Tag, CL> {
+        let delta = self.map.entry(key.clone()).or_default().add(value, tag);
+        Register::make((key, delta.item), delta.tag, delta.length)
+    }
+
+    /// Removes a value for a key.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `value` isn't visible for `key`.
+    pub fn remove_value(
+        &mut self,
+        key: K,
+        value: V,
+        tag: Tag,
+    ) -> Option<Register<(K, V), Tag, CL>> {
+        let delta = self.map.get_mut(&key)?.remove(value, tag)?;
+        Some(Register::make((key, delta.item), delta.tag, delta.length))
+    }
+
+    /// Removes every value visible for a key.
+    ///
+    /// Returns the delta [Register]s that reproduce this change when merged into another replica.
+    /// Values added concurrently on other replicas are not removed.
+    pub fn remove_key(&mut self, key: K, tag: Tag) -> Vec<Register<(K, V), Tag, CL>> {
+        let set = match self.map.get_mut(&key) {
+            Some(set) => set,
+            None => return Vec::new(),
+        };
+        let values: Vec<V> = set.iter().map(|(v, _)| v.clone()).collect();
+        values
+            .into_iter()
+            .filter_map(|v| set.remove(v, tag))
+            .map(|delta| Register::make((key.clone(), delta.item), delta.tag, delta.length))
+            .collect()
+    }
+
+    /// An iterator visiting all visible keys, values and tags in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, Tag)> + '_ {
+        self.map
+            .iter()
+            .flat_map(|(k, set)| set.iter().map(move |(v, tag)| (k, v, tag)))
+    }
+
+    /// An iterator visiting all registers in arbitrary order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<(K, V), Tag, CL>> + '_ {
+        self.map.iter().flat_map(|(k, set)| {
+            set.register_iter()
+                .map(move |r| Register::make((k.clone(), r.item), r.tag, r.length))
+        })
+    }
+
+    /// Merge a delta [Register] into a multimap.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    pub fn merge_register(&mut self, delta: Register<(K, V), Tag, CL>, min_tag: Tag) {
+        if delta.length.is_even() && delta.tag < min_tag {
+            // ignore excessively old remove records
+            return;
+        }
+        self.join_register(delta);
+    }
+
+    // Returns true if the value became present or absent.
+    fn join_register(&mut self, delta: Register<(K, V), Tag, CL>) -> bool {
+        let Register {
+            item: (key, value),
+            tag,
+            length,
+        } = delta;
+        self.map
+            .entry(key)
+            .or_default()
+            .apply_delta(Register::make(value, tag, length))
+    }
+
+    /// Merge a delta [Register] into a multimap, see [MultiMap::merge_register].
+    ///
+    /// Returns the visible effect the delta had on the multimap.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<(K, V), Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<K, V> {
+        if delta.length.is_even() && delta.tag < min_tag {
+            return Change::Ignored(delta.item.0);
+        }
+        let (key, value) = delta.item.clone();
+        let removed = delta.length.is_even();
+        if !self.join_register(delta) {
+            Change::Unchanged(key)
+        } else if removed {
+            Change::Removed(key, value)
+        } else {
+            Change::Added(key, value)
+        }
+    }
+
+    /// Merge two multimaps.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        for delta in other.register_iter() {
+            self.merge_register(delta, min_tag);
+        }
+    }
+
+    /// Merge two multimaps, reporting every value that became visible or hidden, or whose remove
+    /// was ignored.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge_report(&mut self, other: &Self, min_tag: Tag) -> Vec<Change<K, V>> {
+        other
+            .register_iter()
+            .map(|delta| self.merge_register_report(delta, min_tag))
+            .filter(|change| !matches!(change, Change::Unchanged(_)))
+            .collect()
+    }
+
+    /// Filter out old remove tombstone deltas from the multimap.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.map.retain(|_k, set| {
+            set.retain(min_tag);
+            set.register_iter().next().is_some()
+        });
+    }
+}
+
+impl<K, V, Tag, CL> DeltaCrdt for MultiMap<K, V, Tag, CL>
+where
+    K: Key,
+    V: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<(K, V), Tag, CL>;
+
+    fn bottom() -> Self {
+        MultiMap::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.join_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::de::{SeqAccess, Visitor};
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt::Formatter;
+    use std::marker::PhantomData;
+
+    impl<K, V, Tag, CL> Serialize for MultiMap<K, V, Tag, CL>
+    where
+        K: Key + Serialize,
+        V: Key + Serialize,
+        Tag: TagT + Serialize,
+        CL: CausalLength + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq = serializer.serialize_seq(None)?;
+            for member in self.register_iter() {
+                seq.serialize_element(&(member.item.0, member.item.1, member.tag, member.length))?;
+            }
+            seq.end()
+        }
+    }
+
+    struct DeltaVisitor<K, V, Tag, CL>(
+        PhantomData<K>,
+        PhantomData<V>,
+        PhantomData<Tag>,
+        PhantomData<CL>,
+    );
+
+    impl<'de, K, V, Tag, CL> Visitor<'de> for DeltaVisitor<K, V, Tag, CL>
+    where
+        K: Key + Deserialize<'de>,
+        V: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        type Value = MultiMap<K, V, Tag, CL>;
+
+        fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+            formatter.write_str("a tuple of key, value, tag, and causal length")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut map = MultiMap::new();
+            while let Some(d) = seq.next_element::<(K, V, Tag, CL)>()? {
+                map.merge_register(Register::make((d.0, d.1), d.2, d.3), Tag::default());
+            }
+            Ok(map)
+        }
+    }
+
+    impl<'de, K, V, Tag, CL> Deserialize<'de> for MultiMap<K, V, Tag, CL>
+    where
+        K: Key + Deserialize<'de>,
+        V: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_seq(DeltaVisitor(
+                PhantomData,
+                PhantomData,
+                PhantomData,
+                PhantomData,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+    use std::collections::HashSet;
+
+    fn values(
+        mm: &MultiMap<&'static str, &'static str, u32, u16>,
+        key: &str,
+    ) -> HashSet<&'static str> {
+        mm.get_all(&key).map(|(v, _)| *v).collect()
+    }
+
+    #[test]
+    fn test_concurrent_values() {
+        let mut m1: MultiMap<&str, &str, u32, u16> = MultiMap::new();
+        let mut m2 = m1.clone();
+
+        m1.insert("doc", "red", 1);
+        m2.insert("doc", "blue", 1);
+        m1.merge(&m2, 0);
+        m2.merge(&m1, 0);
+        assert_eq!(m1, m2);
+        assert_eq!(
+            values(&m1, "doc"),
+            ["red", "blue"].iter().copied().collect()
+        );
+
+        let d = m1.remove_value("doc", "red", 2).unwrap();
+        assert_eq!(
+            m2.merge_register_report(d, 0),
+            Change::Removed("doc", "red")
+        );
+        assert_eq!(values(&m2, "doc"), ["blue"].iter().copied().collect());
+        assert_eq!(m1.remove_value("other", "red", 2), None);
+    }
+
+    #[test]
+    fn test_remove_key() {
+        let mut m1: MultiMap<&str, &str, u32, u16> = MultiMap::new();
+        m1.insert("doc", "red", 1);
+        m1.insert("doc", "blue", 1);
+        let mut m2 = m1.clone();
+
+        // a concurrent add survives removing the key
+        m2.insert("doc", "green", 2);
+        let deltas = m1.remove_key("doc", 3);
+        assert_eq!(deltas.len(), 2);
+        assert!(!m1.contains_key(&"doc"));
+        for d in deltas {
+            m2.merge_register(d, 0);
+        }
+        assert_eq!(values(&m2, "doc"), ["green"].iter().copied().collect());
+
+        m1.retain(4);
+        assert_eq!(m1.register_iter().count(), 0);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut m: MultiMap<&str, &str, u32, u16> = MultiMap::new();
+        m.insert("doc", "red", 1);
+        m.insert("doc", "blue", 1);
+        m.remove_value("doc", "blue", 2);
+
+        let data = serde_json::to_string(&m).unwrap();
+        let m2: MultiMap<&str, &str, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(m, m2);
+    }
+
+    #[quickcheck]
+    fn test_merge_commutative(xs: Vec<Register<(u8, u8), u8, u8>>) -> bool {
+        let merge = |mut mm: MultiMap<u8, u8, u8, u8>, r: &Register<(u8, u8), u8, u8>| {
+            mm.merge_register(r.clone(), 0);
+            mm
+        };
+        xs.iter().fold(MultiMap::new(), merge) == xs.iter().rfold(MultiMap::new(), merge)
+    }
+}
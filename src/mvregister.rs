@@ -0,0 +1,304 @@
+use super::*;
+use crate::register::Register;
+use std::cmp::{max, Ordering};
+
+#[cfg(feature = "serialization")]
+use serde_derive::Serialize;
+
+/// Causal Length Multi-Value Register
+///
+/// Like [Register], but keeps every value written concurrently at the highest causal length
+/// instead of picking one by tag. The application sees all of them through
+/// [values](MvRegister::values), and collapses them with [resolve](MvRegister::resolve) or any
+/// later [set](MvRegister::set).
+///
+/// Accepts the same [Register] deltas as [Register] itself.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct MvRegister<T, Tag, CL>
+where
+    T: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    // sorted by item, one entry per item
+    values: Vec<(T, Tag)>,
+    length: CL,
+}
+
+impl<T, Tag, CL> MvRegister<T, Tag, CL>
+where
+    T: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new `MvRegister` holding a single value
+    pub fn new(item: T, tag: Tag) -> MvRegister<T, Tag, CL> {
+        MvRegister {
+            values: vec![(item, tag)],
+            length: CL::one(),
+        }
+    }
+
+    /// An iterator visiting the concurrent values and their tags, in item order.
+    ///
+    /// Empty if the register is cleared.
+    pub fn values(&self) -> impl Iterator<Item = (&T, Tag)> + '_ {
+        self.values
+            .iter()
+            .filter(move |_| self.length.is_odd())
+            .map(|(item, tag)| (item, *tag))
+    }
+
+    /// Returns the value if exactly one is present.
+    pub fn get(&self) -> Option<(&T, Tag)> {
+        match self.values.as_slice() {
+            [(item, tag)] if self.length.is_odd() => Some((item, *tag)),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the register holds more than one concurrent value.
+    pub fn is_conflicted(&self) -> bool {
+        self.length.is_odd() && self.values.len() > 1
+    }
+
+    /// Set value, replacing all concurrent values.
+    ///
+    /// Returns the delta [Register] to merge into other replicas.
+    pub fn set(&mut self, item: T, tag: Tag) -> Register<T, Tag, CL> {
+        let tag = self.values.iter().map(|(_, t)| *t).fold(tag, max);
+        if self.length.is_odd() {
+            self.length = self.length + CL::one() + CL::one();
+        } else {
+            self.length = self.length + CL::one();
+        }
+        self.values = vec![(item.clone(), tag)];
+        Register::make(item, tag, self.length)
+    }
+
+    /// Clear value
+    ///
+    /// Returns the delta [Register], or `None` if the register was already empty.
+    pub fn clear(&mut self, tag: Tag) -> Option<Register<T, Tag, CL>> {
+        if self.length.is_even() {
+            return None;
+        }
+        let tag = self.values.iter().map(|(_, t)| *t).fold(tag, max);
+        // keep a single item, so the tombstone can still be sent as a Register
+        let (item, _) = self.values.pop()?;
+        self.length = self.length + CL::one();
+        self.values = vec![(item.clone(), tag)];
+        Some(Register::make(item, tag, self.length))
+    }
+
+    /// Collapse the concurrent values into one, chosen by `f`.
+    ///
+    /// Returns the delta [Register], or `None` if the register is empty.
+    pub fn resolve<F>(&mut self, tag: Tag, f: F) -> Option<Register<T, Tag, CL>>
+    where
+        F: FnOnce(&[(T, Tag)]) -> T,
+    {
+        if self.length.is_even() || self.values.is_empty() {
+            return None;
+        }
+        let item = f(&self.values);
+        Some(self.set(item, tag))
+    }
+
+    /// Merge a delta [Register].
+    pub fn merge_register(&mut self, delta: &Register<T, Tag, CL>) {
+        match delta.length.cmp(&self.length) {
+            Ordering::Greater => {
+                self.length = delta.length;
+                self.values = vec![(delta.item.clone(), delta.tag)];
+            }
+            Ordering::Equal => match self.values.binary_search_by(|(i, _)| i.cmp(&delta.item)) {
+                Ok(idx) => self.values[idx].1 = max(self.values[idx].1, delta.tag),
+                Err(idx) => self.values.insert(idx, (delta.item.clone(), delta.tag)),
+            },
+            Ordering::Less => (),
+        }
+    }
+
+    /// Merge two registers.
+    pub fn merge(&mut self, other: &MvRegister<T, Tag, CL>) {
+        for delta in other.register_iter() {
+            self.merge_register(&delta);
+        }
+    }
+
+    /// An iterator visiting one delta register per concurrent value.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<T, Tag, CL>> + '_ {
+        self.values
+            .iter()
+            .map(move |(item, tag)| Register::make(item.clone(), *tag, self.length))
+    }
+
+    // Accessor for length
+    pub fn length(&self) -> CL {
+        self.length
+    }
+}
+
+impl<T, Tag, CL> DeltaCrdt for MvRegister<T, Tag, CL>
+where
+    T: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<T, Tag, CL>;
+
+    fn bottom() -> Self {
+        MvRegister {
+            values: Vec::new(),
+            length: CL::zero(),
+        }
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        let visible = match delta.length.cmp(&self.length) {
+            Ordering::Greater => match (self.length.is_odd(), delta.length.is_odd()) {
+                (true, true) => {
+                    !matches!(self.values.as_slice(), [(item, _)] if *item == delta.item)
+                }
+                (was, now) => was != now,
+            },
+            Ordering::Equal => {
+                self.length.is_odd()
+                    && self
+                        .values
+                        .binary_search_by(|(i, _)| i.cmp(&delta.item))
+                        .is_err()
+            }
+            Ordering::Less => false,
+        };
+        self.merge_register(&delta);
+        visible
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::{Deserialize, Deserializer};
+
+    // Shape of the serialized register, with `values` in any order
+    #[derive(serde_derive::Deserialize)]
+    #[serde(rename = "MvRegister")]
+    struct Unsorted<T, Tag, CL> {
+        values: Vec<(T, Tag)>,
+        length: CL,
+    }
+
+    /// Sorts the values by item, keeping the greatest tag of any duplicates, as merging them one by
+    /// one would.
+    impl<'de, T, Tag, CL> Deserialize<'de> for MvRegister<T, Tag, CL>
+    where
+        T: Key + Ord + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let Unsorted { mut values, length } =
+                Unsorted::<T, Tag, CL>::deserialize(deserializer)?;
+            // greatest tag first within each item, so dedup keeps it
+            values.sort_by(|(i, t), (j, u)| i.cmp(j).then(u.cmp(t)));
+            values.dedup_by(|(i, _), (j, _)| i == j);
+            Ok(MvRegister { values, length })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[test]
+    fn test_concurrent() {
+        let mut reg1: MvRegister<&str, u32, u16> = MvRegister::new("foo", 0);
+        let mut reg2 = reg1.clone();
+        let mut reg3 = reg1.clone();
+
+        let d1 = reg1.set("bar", 1);
+        let d2 = reg2.set("baz", 2);
+        reg1.merge_register(&d2);
+        reg2.merge_register(&d1);
+        assert_eq!(reg1, reg2);
+        assert!(reg1.is_conflicted());
+        assert_eq!(reg1.get(), None);
+        let values: Vec<(&&str, u32)> = reg1.values().collect();
+        assert_eq!(values, vec![(&"bar", 1), (&"baz", 2)]);
+
+        // an older write is dominated by the concurrent ones
+        reg3.merge(&reg1);
+        assert_eq!(reg3, reg1);
+
+        let d = reg1
+            .resolve(3, |values| values.iter().map(|(v, _)| *v).max().unwrap())
+            .unwrap();
+        reg2.merge_register(&d);
+        assert_eq!(reg2.get(), Some((&"baz", 3)));
+        assert!(!reg2.is_conflicted());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut reg1: MvRegister<&str, u32, u16> = MvRegister::new("foo", 0);
+        let mut reg2 = reg1.clone();
+
+        let delta = reg1.clear(1).unwrap();
+        assert_eq!(reg1.clear(2), None);
+        assert_eq!(reg1.values().count(), 0);
+        reg2.merge_register(&delta);
+        assert_eq!(reg1, reg2);
+
+        let delta = reg2.set("bar", 2);
+        reg1.merge_register(&delta);
+        assert_eq!(reg1.get(), Some((&"bar", 2)));
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_deserialize_unsorted() {
+        let data = r#"{"values":[["baz",1],["bar",2],["baz",3]],"length":1}"#;
+        let reg: MvRegister<&str, u32, u16> = serde_json::from_str(data).unwrap();
+        let values: Vec<(&&str, u32)> = reg.values().collect();
+        assert_eq!(values, vec![(&"bar", 2), (&"baz", 3)]);
+
+        let mut merged = reg.clone();
+        merged.merge_register(&Register::make("baz", 0, 1));
+        assert_eq!(merged, reg);
+        assert_eq!(
+            serde_json::from_str::<MvRegister<&str, u32, u16>>(
+                &serde_json::to_string(&reg).unwrap()
+            )
+            .unwrap(),
+            reg
+        );
+    }
+
+    fn merge(mut acc: MvRegister<u8, u8, u8>, el: &Register<u8, u8, u8>) -> MvRegister<u8, u8, u8> {
+        acc.merge_register(el);
+        acc
+    }
+
+    #[quickcheck]
+    fn is_merge_commutative(xs: Vec<Register<u8, u8, u8>>) -> bool {
+        let left = xs.iter().fold(MvRegister::bottom(), merge);
+        let right = xs.iter().rfold(MvRegister::bottom(), merge);
+        left == right
+    }
+}
@@ -0,0 +1,508 @@
+use super::*;
+use crate::register::Register;
+use crate::set::Set;
+use std::collections::HashMap;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Delta for a [NestedMap]
+///
+/// Deltas of nested maps nest in turn, so a delta for a map of maps is addressed by key path:
+/// `Value { key: outer, delta: Value { key: inner, .. }, .. }`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum NestedDelta<K, D, Tag, CL>
+where
+    K: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Presence of a key
+    Key(Register<K, Tag, CL>),
+    /// Delta for the value of a key, written while the key had causal length `length`
+    Value { key: K, length: CL, delta: D },
+    /// Empty value of a key, created when the key reached causal length `length`
+    ///
+    /// Sent along with the value's own deltas, so that values which were never edited are
+    /// reproduced too.
+    Empty { key: K, length: CL },
+}
+
+/// Causal Length Nested Map
+///
+/// A map whose values are themselves CRDTs. Presence of a key follows the causal length [Set],
+/// while concurrent edits of a value are merged into it rather than replacing it as in [Map].
+///
+/// A removed key keeps its value, so edits concurrent with the remove are not lost. Adding the key
+/// again starts over from an empty value.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NestedMap<K, C, Tag, CL>
+where
+    K: Key,
+    C: DeltaCrdt,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    keys: Set<K, Tag, CL>,
+    // value, and the causal length of the key it was created for
+    values: HashMap<K, (CL, C)>,
+}
+
+impl<K, C, Tag, CL> NestedMap<K, C, Tag, CL>
+where
+    K: Key,
+    C: DeltaCrdt,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new empty `NestedMap`
+    pub fn new() -> NestedMap<K, C, Tag, CL> {
+        NestedMap {
+            keys: Set::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Returns the value for a key, if the key is present.
+    pub fn get(&self, key: &K) -> Option<&C> {
+        if !self.keys.contains(key) {
+            return None;
+        }
+        self.values.get(key).map(|(_, value)| value)
+    }
+
+    /// Returns true if the map contains a key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// An iterator visiting all present keys and values in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &C)> + '_ {
+        self.values
+            .iter()
+            .filter(move |(k, _)| self.keys.contains(*k))
+            .map(|(k, (_, value))| (k, value))
+    }
+
+    // Values exist only for odd causal lengths the key has reached. Deltas for keys which are
+    // unknown, or whose tombstone was dropped, would otherwise leave an orphan value.
+    fn accepts(&self, key: &K, length: CL) -> bool {
+        length.is_odd() && self.keys.length(key).is_some_and(|known| length <= known)
+    }
+
+    // A newer causal length for a key starts over from an empty value.
+    fn value_for(&mut self, key: &K, length: CL) -> Option<&mut C> {
+        if !self.accepts(key, length) {
+            return None;
+        }
+        let e = self
+            .values
+            .entry(key.clone())
+            .or_insert_with(|| (length, C::bottom()));
+        if length > e.0 {
+            *e = (length, C::bottom());
+        }
+        if length == e.0 {
+            Some(&mut e.1)
+        } else {
+            None
+        }
+    }
+
+    /// Add a key with an empty value, or keep the current value if the key is present.
+    ///
+    /// Returns the delta to merge into other replicas.
+    pub fn insert(&mut self, key: K, tag: Tag) -> NestedDelta<K, C::Delta, Tag, CL> {
+        let delta = self.keys.add(key.clone(), tag);
+        self.value_for(&key, delta.length);
+        NestedDelta::Key(delta)
+    }
+
+    /// Removes a key.
+    ///
+    /// Returns the delta to merge into other replicas, or `None` if `key` isn't in the map.
+    pub fn remove(&mut self, key: K, tag: Tag) -> Option<NestedDelta<K, C::Delta, Tag, CL>> {
+        self.keys.remove(key, tag).map(NestedDelta::Key)
+    }
+
+    /// Edit the value for a key, adding the key if it isn't present.
+    ///
+    /// `f` applies the edit and returns the deltas produced by the value's own mutators. Returns
+    /// those deltas addressed to `key`, preceded by the key delta.
+    pub fn update<F, I>(&mut self, key: K, tag: Tag, f: F) -> Vec<NestedDelta<K, C::Delta, Tag, CL>>
+    where
+        F: FnOnce(&mut C) -> I,
+        I: IntoIterator<Item = C::Delta>,
+    {
+        let delta = self.keys.add(key.clone(), tag);
+        let length = delta.length;
+        let mut deltas = vec![NestedDelta::Key(delta)];
+        if let Some(value) = self.value_for(&key, length) {
+            deltas.extend(f(value).into_iter().map(|delta| NestedDelta::Value {
+                key: key.clone(),
+                length,
+                delta,
+            }));
+        }
+        deltas
+    }
+
+    /// An iterator visiting all deltas in arbitrary order.
+    pub fn delta_iter(&self) -> impl Iterator<Item = NestedDelta<K, C::Delta, Tag, CL>> + '_ {
+        self.keys
+            .register_iter()
+            .map(NestedDelta::Key)
+            .chain(self.values.iter().flat_map(|(key, (length, value))| {
+                let empty = NestedDelta::Empty {
+                    key: key.clone(),
+                    length: *length,
+                };
+                std::iter::once(empty).chain(value.deltas().map(move |delta| NestedDelta::Value {
+                    key: key.clone(),
+                    length: *length,
+                    delta,
+                }))
+            }))
+    }
+
+    /// Merge a delta into the map.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored. Value deltas are
+    /// ignored until the key delta they were written after has been merged, so deltas must be
+    /// merged in the order [NestedMap::update] returns them.
+    ///
+    /// Returns the visible effect the delta had on the map. Edits which visibly change a present
+    /// key's value are reported as [Change::Updated].
+    pub fn merge_delta(
+        &mut self,
+        delta: NestedDelta<K, C::Delta, Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<K> {
+        match delta {
+            NestedDelta::Key(delta) => {
+                let length = delta.length;
+                let change = self.keys.merge_register_report(delta, min_tag);
+                if !matches!(change, Change::Ignored(_)) {
+                    self.value_for(change.key(), length);
+                }
+                change
+            }
+            NestedDelta::Value { key, length, delta } => {
+                let visible = match self.value_for(&key, length) {
+                    Some(value) => value.apply_delta(delta),
+                    None => false,
+                };
+                self.updated(key, visible)
+            }
+            NestedDelta::Empty { key, length } => {
+                let reset = self.accepts(&key, length)
+                    && self
+                        .values
+                        .get(&key)
+                        .map_or(true, |(current, _)| length > *current);
+                self.value_for(&key, length);
+                self.updated(key, reset)
+            }
+        }
+    }
+
+    fn updated(&self, key: K, visible: bool) -> Change<K> {
+        if visible && self.keys.contains(&key) {
+            Change::Updated {
+                key,
+                old: (),
+                new: (),
+            }
+        } else {
+            Change::Unchanged(key)
+        }
+    }
+
+    /// Merge two maps, recursing into the values.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        for delta in other.keys.register_iter() {
+            self.merge_delta(NestedDelta::Key(delta), min_tag);
+        }
+        for (key, (length, value)) in other.values.iter() {
+            if let Some(mine) = self.value_for(key, *length) {
+                mine.join(value);
+            }
+        }
+    }
+
+    /// Filter out old remove tombstone deltas from the map along with their values, and from the
+    /// values that are kept.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag)
+    where
+        C: Retain<Tag>,
+    {
+        self.keys.retain(min_tag);
+        let keys = &self.keys;
+        self.values.retain(|key, (_, value)| {
+            let keep = keys.length(key).is_some();
+            if keep {
+                value.retain(min_tag);
+            }
+            keep
+        });
+    }
+}
+
+impl<K, C, Tag, CL> DeltaCrdt for NestedMap<K, C, Tag, CL>
+where
+    K: Key,
+    C: DeltaCrdt,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = NestedDelta<K, C::Delta, Tag, CL>;
+
+    fn bottom() -> Self {
+        NestedMap::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.merge_delta(delta, Tag::default()).is_visible()
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.delta_iter()
+    }
+}
+
+impl<K, D, Tag, CL> Tagged<Tag> for NestedDelta<K, D, Tag, CL>
+where
+    K: Key,
+    D: Tagged<Tag>,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn max_tag(&self) -> Option<Tag> {
+        match self {
+            NestedDelta::Key(register) => Some(register.tag),
+            NestedDelta::Value { delta, .. } => delta.max_tag(),
+            NestedDelta::Empty { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl<K, C, Tag, CL> Serialize for NestedMap<K, C, Tag, CL>
+    where
+        K: Key + Serialize,
+        C: DeltaCrdt + Serialize,
+        Tag: TagT + Serialize,
+        CL: CausalLength + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let values: Vec<_> = self
+                .values
+                .iter()
+                .map(|(key, (length, value))| (key, length, value))
+                .collect();
+            (&self.keys, values).serialize(serializer)
+        }
+    }
+
+    impl<'de, K, C, Tag, CL> Deserialize<'de> for NestedMap<K, C, Tag, CL>
+    where
+        K: Key + Deserialize<'de>,
+        C: DeltaCrdt + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let (keys, values) = <(Set<K, Tag, CL>, Vec<(K, CL, C)>)>::deserialize(deserializer)?;
+            Ok(NestedMap {
+                keys,
+                values: values
+                    .into_iter()
+                    .map(|(key, length, value)| (key, (length, value)))
+                    .collect(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay_in_order;
+
+    type Tags = NestedMap<&'static str, Set<&'static str, u32, u16>, u32, u16>;
+    type Views = NestedMap<&'static str, Counter<&'static str, u32, i64>, u32, u16>;
+    type Pages = NestedMap<&'static str, Views, u32, u16>;
+
+    #[test]
+    fn test_concurrent_inner_edits() {
+        let mut m1: Tags = NestedMap::new();
+        let mut m2: Tags = NestedMap::new();
+
+        let mut deltas = m1.update("doc", 1, |s| vec![s.add("red", 1)]);
+        deltas.extend(m2.update("doc", 1, |s| vec![s.add("blue", 1)]));
+        m1.merge(&m2, 0);
+
+        // concurrent edits of the same value both survive
+        let set = m1.get(&"doc").unwrap();
+        assert!(set.contains("red"));
+        assert!(set.contains("blue"));
+        replay_in_order(&m1, deltas);
+    }
+
+    #[test]
+    fn test_remove_and_readd() {
+        let mut m1: Tags = NestedMap::new();
+        m1.update("doc", 1, |s| vec![s.add("red", 1)]);
+        let mut m2 = m1.clone();
+
+        // an edit concurrent with the remove is kept
+        m1.remove("doc", 2).unwrap();
+        m2.update("doc", 2, |s| vec![s.add("blue", 2)]);
+        m1.merge(&m2, 0);
+        assert_eq!(m1.get(&"doc"), None);
+
+        // adding the key again starts from an empty value
+        m1.insert("doc", 3);
+        assert_eq!(m1.get(&"doc").unwrap().iter().count(), 0);
+        m2.merge(&m1, 0);
+        assert_eq!(m1, m2);
+    }
+
+    #[test]
+    fn test_replay_deltas() {
+        let mut m: Tags = NestedMap::new();
+        m.insert("k", 1);
+        m.remove("k", 2);
+        m.update("doc", 1, |s| vec![s.add("red", 1)]);
+        replay_in_order(&m, m.delta_iter().collect());
+
+        // a remove of an unknown key doesn't create a value
+        let mut other: Tags = NestedMap::new();
+        other.merge_delta(m.remove("doc", 3).unwrap(), 0);
+        assert_eq!(other.values.len(), 0);
+    }
+
+    #[test]
+    fn test_value_changes() {
+        let mut m1: Tags = NestedMap::new();
+        let deltas = m1.update("doc", 1, |s| vec![s.add("red", 1)]);
+        let mut m2: Tags = NestedMap::new();
+        let changes: Vec<_> = deltas
+            .iter()
+            .cloned()
+            .map(|delta| m2.merge_delta(delta, 0))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added("doc", ()),
+                Change::Updated {
+                    key: "doc",
+                    old: (),
+                    new: ()
+                }
+            ]
+        );
+
+        // merging the same edit again changes nothing
+        assert_eq!(
+            m2.merge_delta(deltas[1].clone(), 0),
+            Change::Unchanged("doc")
+        );
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut m: Tags = NestedMap::new();
+        m.update("doc", 1, |s| vec![s.add("red", 1), s.add("blue", 1)]);
+        m.update("doc", 2, |s| s.remove("red", 2));
+        let late = m.update("old", 2, |s| vec![s.add("red", 2)]);
+        m.remove("old", 3).unwrap();
+
+        // tombstones inside the kept values are dropped too
+        m.retain(4);
+        assert_eq!(m.keys.register_iter().count(), 1);
+        assert_eq!(m.get(&"doc").unwrap().register_iter().count(), 1);
+        assert_eq!(m.values.len(), 1);
+
+        // a late edit of a collected key doesn't bring back its value
+        for delta in late.into_iter().skip(1) {
+            assert_eq!(m.merge_delta(delta, 4), Change::Unchanged("old"));
+        }
+        assert_eq!(m.values.len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let mut m1: Tags = NestedMap::new();
+        let deltas = m1.update("doc", 1, |s| vec![s.add("red", 1)]);
+
+        // value deltas ahead of their key delta are rejected
+        let mut m2: Tags = NestedMap::new();
+        assert_eq!(
+            m2.merge_delta(deltas[1].clone(), 0),
+            Change::Unchanged("doc")
+        );
+        assert_eq!(m2.values.len(), 0);
+        for delta in deltas {
+            m2.merge_delta(delta, 0);
+        }
+        assert_eq!(m1, m2);
+    }
+
+    #[test]
+    fn test_key_path() {
+        let mut outer: Pages = NestedMap::new();
+        let deltas = outer.update("page", 1, |inner| {
+            inner.update("views", 1, |c| c.increment("a", 3, 1))
+        });
+        assert!(matches!(
+            &deltas[2],
+            NestedDelta::Value {
+                key: "page",
+                delta: NestedDelta::Value { key: "views", .. },
+                ..
+            }
+        ));
+
+        let mut replica: Pages = NestedMap::new();
+        for delta in deltas {
+            replica.merge_delta(delta, 0);
+        }
+        assert_eq!(
+            replica.get(&"page").unwrap().get(&"views").unwrap().value(),
+            Some(3)
+        );
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut m: NestedMap<u8, Set<u8, u32, u16>, u32, u16> = NestedMap::new();
+        m.update(1, 1, |s| vec![s.add(2, 1)]);
+        m.remove(1, 2);
+
+        let data = serde_json::to_string(&m).unwrap();
+        let m2: NestedMap<u8, Set<u8, u32, u16>, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(m, m2);
+    }
+}
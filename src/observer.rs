@@ -0,0 +1,277 @@
+use super::*;
+use crate::storage::Backend;
+use std::ops::Deref;
+
+/// Receives the visible changes made to an [Observed] collection.
+///
+/// Implemented for any `FnMut(&Change<K, V>)` closure.
+pub trait Observer<K, V = ()> {
+    /// Called once for every change that altered the visible state of the collection.
+    fn notify(&mut self, change: &Change<K, V>);
+}
+
+impl<K, V, F> Observer<K, V> for F
+where
+    F: FnMut(&Change<K, V>),
+{
+    fn notify(&mut self, change: &Change<K, V>) {
+        self(change)
+    }
+}
+
+/// A collection which reports its changes through [Change] values.
+pub trait Observable {
+    /// Key type of the reported changes
+    type Key;
+    /// Value type of the reported changes
+    type Value;
+}
+
+impl<T, Tag, CL, S> Observable for Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T>,
+{
+    type Key = T;
+    type Value = ();
+}
+
+impl<K, V, Tag, CL, S> Observable for Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+    type Key = K;
+    type Value = V;
+}
+
+/// Observed collection
+///
+/// Wraps a [Set] or [Map], and notifies the subscribed observers of every visible change, whether
+/// made by a local mutation or by merging a remote delta. Read access is available through
+/// `Deref`; all mutations must go through the wrapper so they can be observed.
+pub struct Observed<C>
+where
+    C: Observable,
+{
+    inner: C,
+    observers: Vec<Box<dyn Observer<C::Key, C::Value>>>,
+}
+
+impl<C> Observed<C>
+where
+    C: Observable,
+{
+    /// Wrap a collection, without any observers
+    pub fn new(inner: C) -> Observed<C> {
+        Observed {
+            inner,
+            observers: Vec::new(),
+        }
+    }
+
+    /// Register an observer to be notified of future changes
+    pub fn subscribe<O>(&mut self, observer: O)
+    where
+        O: Observer<C::Key, C::Value> + 'static,
+    {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Unwrap the collection, dropping all observers
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn notify(&mut self, change: &Change<C::Key, C::Value>) {
+        if change.is_visible() {
+            for observer in self.observers.iter_mut() {
+                observer.notify(change);
+            }
+        }
+    }
+}
+
+impl<C> Deref for Observed<C>
+where
+    C: Observable,
+{
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<T, Tag, CL, S> Observed<Set<T, Tag, CL, S>>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T>,
+{
+    /// Add a value to the set, see [Set::add].
+    pub fn add(&mut self, member: T, tag: Tag) -> Register<T, Tag, CL> {
+        let before = self.inner.contains(&member);
+        let delta = self.inner.add(member.clone(), tag);
+        if !before {
+            self.notify(&Change::Added(member, ()));
+        }
+        delta
+    }
+
+    /// Remove a value from the set, see [Set::remove].
+    pub fn remove(&mut self, member: T, tag: Tag) -> Option<Register<T, Tag, CL>> {
+        let before = self.inner.contains(&member);
+        let delta = self.inner.remove(member.clone(), tag);
+        if before {
+            self.notify(&Change::Removed(member, ()));
+        }
+        delta
+    }
+
+    /// Merge a delta [Register] into the set, see [Set::merge_register_report].
+    pub fn merge_register(&mut self, delta: Register<T, Tag, CL>, min_tag: Tag) -> Change<T> {
+        let change = self.inner.merge_register_report(delta, min_tag);
+        self.notify(&change);
+        change
+    }
+
+    /// Merge another set, see [Set::merge].
+    pub fn merge(&mut self, other: &Set<T, Tag, CL, S>, min_tag: Tag) {
+        for delta in other.register_iter() {
+            self.merge_register(delta, min_tag);
+        }
+    }
+
+    /// Filter out old remove tombstones, see [Set::retain]. Never changes visible state.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.inner.retain(min_tag);
+    }
+}
+
+impl<K, V, Tag, CL, S> Observed<Map<K, V, Tag, CL, S>>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+    /// Insert a key and value into the map, see [Map::insert].
+    pub fn insert(&mut self, key: K, value: V, tag: Tag) -> Register<(K, V), Tag, CL> {
+        let before = self.inner.get(&key).map(|(v, _)| v.clone());
+        let delta = self.inner.insert(key.clone(), value.clone(), tag);
+        match before {
+            None => self.notify(&Change::Added(key, value)),
+            Some(old) if old != value => self.notify(&Change::Updated {
+                key,
+                old,
+                new: value,
+            }),
+            _ => (),
+        }
+        delta
+    }
+
+    /// Remove a key from the map, see [Map::remove].
+    pub fn remove(&mut self, key: K, tag: Tag) -> Option<Register<(K, V), Tag, CL>> {
+        let before = self.inner.get(&key).map(|(v, _)| v.clone());
+        let delta = self.inner.remove(key.clone(), tag);
+        if let Some(old) = before {
+            self.notify(&Change::Removed(key, old));
+        }
+        delta
+    }
+
+    /// Merge a delta [Register] into the map, see [Map::merge_register_report].
+    pub fn merge_register(
+        &mut self,
+        delta: Register<(K, V), Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<K, V> {
+        let change = self.inner.merge_register_report(delta, min_tag);
+        self.notify(&change);
+        change
+    }
+
+    /// Merge another map, see [Map::merge].
+    pub fn merge(&mut self, other: &Map<K, V, Tag, CL, S>, min_tag: Tag) {
+        for delta in other.register_iter() {
+            self.merge_register(delta, min_tag);
+        }
+    }
+
+    /// Filter out old remove tombstones, see [Map::retain]. Never changes visible state.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.inner.retain(min_tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_observed_set() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut cls: Observed<Set<&str, u32, u16>> = Observed::new(Set::new());
+        let log = seen.clone();
+        cls.subscribe(move |c: &Change<&'static str>| log.borrow_mut().push(c.clone()));
+
+        cls.add("foo", 1);
+        cls.add("foo", 1);
+        cls.remove("foo", 2);
+        cls.remove("bar", 2);
+
+        let mut other: Set<&str, u32, u16> = Set::new();
+        other.add("bar", 3);
+        cls.merge(&other, 0);
+
+        assert!(cls.contains("bar"));
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                Change::Added("foo", ()),
+                Change::Removed("foo", ()),
+                Change::Added("bar", ()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observed_map() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut m: Observed<Map<&str, u32, u32, u16>> = Observed::new(Map::new());
+        let log = seen.clone();
+        m.subscribe(move |c: &Change<&'static str, u32>| log.borrow_mut().push(c.clone()));
+
+        m.insert("foo", 128, 1);
+        m.insert("foo", 128, 1);
+        m.insert("foo", 256, 2);
+
+        let mut other = (*m).clone();
+        other.remove("foo", 3);
+        m.merge(&other, 0);
+
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                Change::Added("foo", 128),
+                Change::Updated {
+                    key: "foo",
+                    old: 128,
+                    new: 256
+                },
+                Change::Removed("foo", 256),
+            ]
+        );
+    }
+}
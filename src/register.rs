@@ -9,7 +9,7 @@ use serde_derive::{Deserialize, Serialize};
 /// Register implements a single member for the set described in the paper, with the addition of a
 /// tag. Sort of acts like a CRDT Option type. Register doesn't directly use the tag, but it also
 /// acts as a delta for the other CRDT's in this crate.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
 pub struct Register<T, Tag, CL>
 where
@@ -57,7 +57,9 @@ where
     }
 
     /// Set value
-    pub fn set(&mut self, item: T, tag: Tag) {
+    ///
+    /// Returns the updated register, which is also the delta to merge into other replicas.
+    pub fn set(&mut self, item: T, tag: Tag) -> Register<T, Tag, CL> {
         self.item = item;
         self.tag = max(self.tag, tag);
 
@@ -66,13 +68,19 @@ where
         } else {
             self.length = self.length + CL::one();
         }
+        self.clone()
     }
 
     /// Clear value
-    pub fn clear(&mut self, tag: Tag) {
+    ///
+    /// Returns the updated register as a delta, or `None` if the register was already empty.
+    pub fn clear(&mut self, tag: Tag) -> Option<Register<T, Tag, CL>> {
         if self.length.is_odd() {
             self.length = self.length + CL::one();
             self.tag = max(self.tag, tag);
+            Some(self.clone())
+        } else {
+            None
         }
     }
 
@@ -99,20 +107,75 @@ where
     CL: CausalLength,
 {
     /// Merge two register values
+    ///
+    /// Keeps whichever register is greatest by causal length, then tag, then item. Ordering on
+    /// all three makes merge a join: commutative, associative and idempotent over the whole
+    /// register, so replicas agree on the tag of a cleared register as well as on its value.
     pub fn merge(&mut self, other: &Register<T, Tag, CL>) {
-        if other.length > self.length && other.length.is_odd() {
-            self.item = other.item.clone();
-            self.tag = other.tag;
-        }
-        if other.length == self.length {
-            if other.tag > self.tag {
-                self.item = other.item.clone();
-                self.tag = max(self.tag, other.tag);
-            } else if other.tag == self.tag && other.item > self.item {
-                self.item = other.item.clone();
-            }
+        if (other.length, other.tag, &other.item) > (self.length, self.tag, &self.item) {
+            self.clone_from(other);
         }
-        self.length = max(self.length, other.length);
+    }
+
+    // Merge, returning true if the item became present or absent, or a present item changed.
+    pub(crate) fn merge_visible(&mut self, other: &Register<T, Tag, CL>) -> bool {
+        let visible = (other.length, other.tag, &other.item) > (self.length, self.tag, &self.item)
+            && (self.length.is_odd() != other.length.is_odd()
+                || (other.length.is_odd() && other.item != self.item));
+        self.merge(other);
+        visible
+    }
+}
+
+impl<T, Tag, CL> DeltaCrdt for Register<T, Tag, CL>
+where
+    T: Key + Ord + Default,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<T, Tag, CL>;
+
+    fn bottom() -> Self {
+        Register::make(T::default(), Tag::default(), CL::zero())
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.merge_visible(&delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        std::iter::once(self.clone())
+    }
+}
+
+impl<T, Tag, CL> Coalesce for Register<T, Tag, CL>
+where
+    T: Key + Ord + Default,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    // a register has only one key
+    type DeltaKey = ();
+
+    fn delta_key(_delta: &Self::Delta) {}
+
+    fn coalesce(into: &mut Self::Delta, delta: &Self::Delta) {
+        into.merge(delta);
+    }
+}
+
+impl<T, Tag, CL> Tagged<Tag> for Register<T, Tag, CL>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn max_tag(&self) -> Option<Tag> {
+        Some(self.tag)
     }
 }
 
@@ -152,6 +215,37 @@ mod tests {
         assert_eq!(reg1.get(), Some((&"bar", 2)));
     }
 
+    #[test]
+    fn test_deltas() {
+        let mut reg1: Register<&str, u32, u16> = Register::new("foo", 0);
+        let mut reg2 = reg1.clone();
+
+        let delta = reg1.set("bar", 1);
+        reg2.merge(&delta);
+        assert_eq!(reg1, reg2);
+
+        let delta = reg1.clear(2).unwrap();
+        reg2.merge(&delta);
+        assert_eq!(reg1, reg2);
+        assert_eq!(reg1.clear(3), None);
+    }
+
+    #[test]
+    fn test_merge_clear() {
+        let mut reg1: Register<&str, u32, u16> = Register::new("foo", 3);
+        let mut reg2 = reg1.clone();
+
+        // the clear wins on length, and brings its tag along even though it's lower
+        let delta = reg2.clear(1).unwrap();
+        reg1.merge(&delta);
+        assert_eq!(reg1, reg2);
+        assert_eq!(reg1.get(), None);
+
+        let mut reg3: Register<&str, u32, u16> = Register::make("bar", 1, 2);
+        reg3.merge(&Register::make("foo", 2, 2));
+        assert_eq!(reg3, Register::make("foo", 2, 2));
+    }
+
     #[cfg(feature = "serialization")]
     #[test]
     fn test_serialization() {
@@ -172,6 +266,18 @@ mod tests {
         left.get() == right.get()
     }
 
+    #[quickcheck]
+    fn is_merge_join(
+        a: Register<u8, u8, u8>,
+        b: Register<u8, u8, u8>,
+        c: Register<u8, u8, u8>,
+    ) -> bool {
+        let ab_c = merge(merge(a.clone(), &b), &c);
+        let a_bc = merge(a.clone(), &merge(b.clone(), &c));
+        let ba = merge(b.clone(), &a);
+        ab_c == a_bc && merge(a.clone(), &b) == ba && merge(a.clone(), &a) == a
+    }
+
     #[test]
     fn test_fup() {
         let xs = vec![
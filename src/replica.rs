@@ -0,0 +1,242 @@
+use super::*;
+use crate::register::Register;
+use crate::storage::Backend;
+use std::ops::Deref;
+
+/// CRDT replica which stamps its own tags
+///
+/// Wraps any CRDT in this crate together with a [Clock]. Every local operation is stamped with a
+/// fresh tick of the clock, and every tag merged from another replica advances the clock past it,
+/// so a local operation is always tagged after everything this replica has seen.
+///
+/// Read access is available through `Deref`. [Set], [Map] and [Register] have tag-less versions
+/// of their mutators; any other CRDT can be edited with [Replica::stamp]. Merging advances the
+/// clock for any CRDT whose deltas are [Tagged], as the deltas of every CRDT in this crate are.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Replica<C, K>
+where
+    K: Clock,
+{
+    crdt: C,
+    clock: K,
+}
+
+impl<C, K> Replica<C, K>
+where
+    K: Clock,
+{
+    /// Wrap a CRDT, stamping operations from `clock`
+    pub fn new(crdt: C, clock: K) -> Replica<C, K> {
+        Replica { crdt, clock }
+    }
+
+    /// Returns the current value of the clock.
+    pub fn clock(&self) -> K {
+        self.clock
+    }
+
+    /// Unwrap the CRDT, dropping the clock
+    pub fn into_inner(self) -> C {
+        self.crdt
+    }
+
+    /// Apply a local operation, passing it a fresh tag.
+    ///
+    /// Returns whatever `f` returns, usually the deltas it produced.
+    pub fn stamp<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut C, K) -> R,
+    {
+        let tag = self.clock.tick();
+        f(&mut self.crdt, tag)
+    }
+}
+
+impl<C, K> Deref for Replica<C, K>
+where
+    K: Clock,
+{
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.crdt
+    }
+}
+
+impl<C, K> Replica<C, K>
+where
+    C: DeltaCrdt,
+    C::Delta: Tagged<K>,
+    K: Clock,
+{
+    /// Merge a delta from another replica, advancing the clock past its tags.
+    pub fn apply_delta(&mut self, delta: C::Delta) {
+        self.observe(&delta);
+        self.crdt.apply_delta(delta);
+    }
+
+    /// Merge the full state of another replica, advancing the clock past all of its tags.
+    pub fn join(&mut self, other: &C) {
+        for delta in other.deltas() {
+            self.observe(&delta);
+        }
+        self.crdt.join(other);
+    }
+
+    fn observe(&mut self, delta: &C::Delta) {
+        if let Some(tag) = delta.max_tag() {
+            self.clock.observe(tag);
+        }
+    }
+}
+
+impl<T, K, CL, S> Replica<Set<T, K, CL, S>, K>
+where
+    T: Key,
+    K: Clock,
+    CL: CausalLength,
+    S: Backend<T>,
+{
+    /// Add a value to the set, see [Set::add].
+    pub fn add(&mut self, member: T) -> Register<T, K, CL> {
+        self.stamp(|set, tag| set.add(member, tag))
+    }
+
+    /// Remove a value from the set, see [Set::remove].
+    pub fn remove(&mut self, member: T) -> Option<Register<T, K, CL>> {
+        self.stamp(|set, tag| set.remove(member, tag))
+    }
+
+    /// Merge a delta [Register] into the set, advancing the clock past its tag, see
+    /// [Set::merge_register].
+    pub fn merge_register(&mut self, delta: Register<T, K, CL>, min_tag: K) {
+        self.clock.observe(delta.tag);
+        self.crdt.merge_register(delta, min_tag);
+    }
+}
+
+impl<Q, V, K, CL, S> Replica<Map<Q, V, K, CL, S>, K>
+where
+    Q: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    K: Clock,
+    CL: CausalLength,
+    S: Backend<Q>,
+{
+    /// Insert a key and value into the map, see [Map::insert].
+    pub fn insert(&mut self, key: Q, value: V) -> Register<(Q, V), K, CL> {
+        self.stamp(|map, tag| map.insert(key, value, tag))
+    }
+
+    /// Remove a key from the map, see [Map::remove].
+    pub fn remove(&mut self, key: Q) -> Option<Register<(Q, V), K, CL>> {
+        self.stamp(|map, tag| map.remove(key, tag))
+    }
+
+    /// Merge a delta [Register] into the map, advancing the clock past its tag, see
+    /// [Map::merge_register].
+    pub fn merge_register(&mut self, delta: Register<(Q, V), K, CL>, min_tag: K) {
+        self.clock.observe(delta.tag);
+        self.crdt.merge_register(delta, min_tag);
+    }
+}
+
+impl<T, K, CL> Replica<Register<T, K, CL>, K>
+where
+    T: Key,
+    K: Clock,
+    CL: CausalLength,
+{
+    /// Set value, see [Register::set].
+    pub fn set(&mut self, item: T) -> Register<T, K, CL> {
+        self.stamp(|register, tag| register.set(item, tag))
+    }
+
+    /// Clear value, see [Register::clear].
+    pub fn clear(&mut self) -> Option<Register<T, K, CL>> {
+        self.stamp(|register, tag| register.clear(tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Lamport = LamportClock<u8>;
+    type Tags = Replica<Set<&'static str, Lamport, u16>, Lamport>;
+    type Sizes = Replica<Map<&'static str, u32, Lamport, u16>, Lamport>;
+
+    #[test]
+    fn test_stamps_after_remote() {
+        let mut r1: Tags = Replica::new(Set::new(), LamportClock::new(1));
+        let mut r2: Tags = Replica::new(Set::new(), LamportClock::new(2));
+
+        let d1 = r1.add("foo");
+        let d2 = r1.add("bar");
+        assert!(d1.tag() < d2.tag());
+
+        // a remote tag moves the clock forward, so the next local op is tagged after it
+        r2.merge_register(d2.clone(), LamportClock::default());
+        let d3 = r2.remove("bar").unwrap();
+        assert!(d2.tag() < d3.tag());
+
+        r1.apply_delta(d3);
+        assert!(!r1.contains("bar"));
+        assert!(r1.contains("foo"));
+        assert_eq!(r1.clock().time(), 3);
+    }
+
+    #[test]
+    fn test_merge_map() {
+        let mut r1: Sizes = Replica::new(Map::new(), LamportClock::new(1));
+        let mut r2: Sizes = Replica::new(Map::new(), LamportClock::new(2));
+        for i in 0..5 {
+            r1.insert("foo", i);
+        }
+        r2.insert("foo", 10);
+
+        // after a full merge, r2's next write is stamped after everything r1 had done
+        r2.join(&r1);
+        r2.insert("foo", 20);
+        r1.join(&r2);
+        let (value, tag) = r1.get("foo").unwrap();
+        assert_eq!((*value, tag.time()), (20, 6));
+        assert_eq!(r2.remove("foo").map(|d| d.item().1), Some(20));
+    }
+
+    #[test]
+    fn test_stamp() {
+        let mut list: Replica<List<char, u8, Lamport, u16>, Lamport> =
+            Replica::new(List::new(), LamportClock::new(1));
+        let mut deltas = vec![list.stamp(|l, tag| l.push('b', 1, tag))];
+        deltas.push(list.stamp(|l, tag| l.insert(0, 'a', 1, tag)));
+
+        let mut other = Replica::new(List::new(), LamportClock::new(2));
+        for delta in deltas {
+            other.apply_delta(delta);
+        }
+        assert!(other.iter().eq(list.iter()));
+        assert_eq!(other.clock().time(), 2);
+    }
+
+    #[test]
+    fn test_nested_map() {
+        type Docs = NestedMap<&'static str, Set<&'static str, Lamport, u16>, Lamport, u16>;
+        let mut r1: Replica<Docs, Lamport> = Replica::new(NestedMap::new(), LamportClock::new(1));
+        let mut deltas = Vec::new();
+        for tag in ["red", "blue", "green"] {
+            deltas.extend(r1.stamp(|m, t| m.update("doc", t, |s| vec![s.add(tag, t)])));
+        }
+
+        // the tags of inner deltas advance the clock too
+        let mut r2: Replica<Docs, Lamport> = Replica::new(NestedMap::new(), LamportClock::new(2));
+        for delta in deltas {
+            r2.apply_delta(delta);
+        }
+        assert_eq!(r2.clock().time(), 3);
+        let mut r3: Replica<Docs, Lamport> = Replica::new(NestedMap::new(), LamportClock::new(3));
+        r3.join(&r1);
+        assert_eq!(r3.clock().time(), 3);
+        assert_eq!(*r3, *r2);
+    }
+}
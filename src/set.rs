@@ -1,9 +1,12 @@
 use super::*;
 use crate::register::Register;
+use crate::storage::{Backend, Storage, TagIndex};
 use std::borrow::Borrow;
 use std::cmp::max;
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::ops::RangeBounds;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct SubRegister<Tag, CL>
@@ -19,36 +22,93 @@ where
 ///
 /// Set implements the set described in the paper, with the addition of a tag. Set only uses the
 /// tag for garbage collection of old removed members.
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub struct Set<T, Tag, CL>
+///
+/// Members are stored in a `HashMap` by default. Use [OrderedSet] for a set which visits members in
+/// order, and supports range queries.
+#[derive(Clone, Debug)]
+pub struct Set<T, Tag, CL, S = RandomState>
 where
     T: Key,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<T>,
 {
-    // HashMap, because the "set" needs to allow mutating the tag and causal length.
-    map: HashMap<T, SubRegister<Tag, CL>>,
+    // A map, because the "set" needs to allow mutating the tag and causal length.
+    map: S::Store<SubRegister<Tag, CL>>,
+    index: TagIndex<T, Tag, S, S::Store<()>>,
 }
 
-impl<T, Tag, CL> Set<T, Tag, CL>
+/// Causal Length Set which visits members in order
+pub type OrderedSet<T, Tag, CL> = Set<T, Tag, CL, Ordered>;
+
+impl<T, Tag, CL, S> PartialEq for Set<T, Tag, CL, S>
 where
     T: Key,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<T>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self.map.iter().all(|(k, v)| other.map.get(k) == Some(v))
+    }
+}
+
+impl<T, Tag, CL, S> Eq for Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T>,
+{
+}
+
+impl<T, Tag, CL, S> Default for Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T> + Default,
+{
+    fn default() -> Self {
+        Set::new()
+    }
+}
+
+impl<T, Tag, CL, S> Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T>,
 {
     /// Create a new empty `Set`
-    pub fn new() -> Set<T, Tag, CL> {
+    pub fn new() -> Set<T, Tag, CL, S>
+    where
+        S: Default,
+    {
+        Set::with_backend(S::default())
+    }
+
+    /// Create a new empty `Set` which stores its members using `backend`.
+    pub fn with_backend(backend: S) -> Set<T, Tag, CL, S> {
         Set {
-            map: HashMap::new(),
+            map: backend.store(),
+            index: TagIndex::of(backend, std::iter::empty()),
         }
     }
 
+    /// Returns the backend storing the set's members.
+    pub fn backend(&self) -> S {
+        S::of(&self.map)
+    }
+
     /// Returns `None` if `member` is not present in the set. If present returns `Some(Tag)`
     pub fn get<Q>(&self, member: Q) -> Option<Tag>
     where
         Q: Borrow<T>,
     {
-        if let Some(e) = self.map.get(member.borrow()).to_owned() {
+        if let Some(e) = self.map.get(member.borrow()) {
             if e.length.is_odd() {
                 return Some(e.tag);
             }
@@ -64,36 +124,53 @@ where
         self.get(member).is_some()
     }
 
+    // Causal length of a member, present or removed, while its register is kept.
+    pub(crate) fn length(&self, member: &T) -> Option<CL> {
+        self.map.get(member).map(|e| e.length)
+    }
+
     /// Add a value to a set.
-    pub fn add(&mut self, member: T, tag: Tag) {
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica.
+    pub fn add(&mut self, member: T, tag: Tag) -> Register<T, Tag, CL> {
         let one: CL = CL::one();
-        let mut e = self
+        let mut old = None;
+        let e = self
             .map
-            .entry(member)
-            .or_insert(SubRegister { tag, length: one });
-        // s{e |-> s(e)+1} if even
-        //s if odd s(e)
-        if e.length.is_even() {
-            e.length = e.length + one;
-        }
-        // always use the max value of tag
-        e.tag = max(e.tag, tag);
+            .upsert(member.clone(), SubRegister { tag, length: one }, |e, _| {
+                // s{e |-> s(e)+1} if even
+                //s if odd s(e)
+                if e.length.is_even() {
+                    e.length = e.length + one;
+                }
+                // always use the max value of tag
+                old = Some(e.tag);
+                e.tag = max(e.tag, tag);
+            });
+        self.index.update(&member, old, Some(e.tag));
+        Register::make(member, e.tag, e.length)
     }
 
     /// Removes a value from the set.
-    pub fn remove(&mut self, member: T, tag: Tag) {
-        self.map.entry(member).and_modify(|e| {
-            // {} if even(s(e))
-            // { e |-> s(e) + 1 } if odd(s(e))
-            if e.length.is_odd() {
-                e.length = e.length + CL::one()
-            }
-            e.tag = max(e.tag, tag);
-        });
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `member` isn't in the set.
+    pub fn remove(&mut self, member: T, tag: Tag) -> Option<Register<T, Tag, CL>> {
         // ignore attempts to remove items that aren't present...
+        let e = self.map.get_mut(&member)?;
+        // {} if even(s(e))
+        // { e |-> s(e) + 1 } if odd(s(e))
+        if e.length.is_even() {
+            return None;
+        }
+        e.length = e.length + CL::one();
+        let old = e.tag;
+        e.tag = max(e.tag, tag);
+        self.index.update(&member, Some(old), Some(e.tag));
+        Some(Register::make(member, e.tag, e.length))
     }
 
-    /// An iterator visiting all elements and tags in arbitrary order.
+    /// An iterator visiting all elements and tags in storage order.
     pub fn iter(&self) -> impl Iterator<Item = (&T, Tag)> + '_ {
         self.map
             .iter()
@@ -101,8 +178,8 @@ where
             .map(|(k, v)| (k, v.tag))
     }
 
-    /// An iterator visiting all registers in arbitrary order.
-    pub fn register_iter(&self) -> impl Iterator<Item = <Self as DeltaCrdt>::Delta> + '_ {
+    /// An iterator visiting all registers in storage order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<T, Tag, CL>> + '_ {
         self.map.iter().map(|(k, v)| Register {
             item: k.clone(),
             tag: v.tag,
@@ -113,22 +190,53 @@ where
     /// Merge a delta [Register] into a set.
     ///
     /// Remove registers with a tag value less than `min_tag` will be ignored.
-    pub fn merge_register(&mut self, delta: <Self as DeltaCrdt>::Delta, min_tag: Tag) {
+    pub fn merge_register(&mut self, delta: Register<T, Tag, CL>, min_tag: Tag) {
         if delta.length.is_even() && delta.tag < min_tag {
             // ignore excessively old remove records
             return;
         }
+        self.join_register(delta);
+    }
+
+    // Returns true if the member became present or absent.
+    fn join_register(&mut self, delta: Register<T, Tag, CL>) -> bool {
         let Register { item, tag, length } = delta;
-        match self.map.entry(item) {
-            Entry::Occupied(mut e) => {
-                let e = e.get_mut();
+        let mut old = None;
+        let e = self
+            .map
+            .upsert(item.clone(), SubRegister { tag, length }, |e, _| {
                 // (s⊔s′)(e) = max(s(e),s′(e))
+                old = Some((e.tag, e.length));
                 e.tag = max(e.tag, tag);
                 e.length = max(e.length, length);
-            }
-            Entry::Vacant(e) => {
-                e.insert(SubRegister { tag, length });
-            }
+            });
+        let visible = match old {
+            Some((_, length)) => length.is_odd() != e.length.is_odd(),
+            None => e.length.is_odd(),
+        };
+        self.index
+            .update(&item, old.map(|(tag, _)| tag), Some(e.tag));
+        visible
+    }
+
+    /// Merge a delta [Register] into a set, see [Set::merge_register].
+    ///
+    /// Returns the visible effect the delta had on the set.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<T, Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<T> {
+        if delta.length.is_even() && delta.tag < min_tag {
+            return Change::Ignored(delta.item);
+        }
+        let item = delta.item.clone();
+        let before = self.contains(&item);
+        self.merge_register(delta, min_tag);
+        match (before, self.contains(&item)) {
+            (false, true) => Change::Added(item, ()),
+            (true, false) => Change::Removed(item, ()),
+            _ => Change::Unchanged(item),
         }
     }
 
@@ -141,38 +249,221 @@ where
         }
     }
 
+    /// Merge two sets, reporting every member that became visible or hidden, or whose remove was
+    /// ignored.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge_report(&mut self, other: &Self, min_tag: Tag) -> Vec<Change<T>> {
+        other
+            .register_iter()
+            .map(|delta| self.merge_register_report(delta, min_tag))
+            .filter(|change| !matches!(change, Change::Unchanged(_)))
+            .collect()
+    }
+
+    /// Drop the index of members by tag, so [Set::registers_since] scans every register.
+    ///
+    /// The index costs an entry per member, stored with the set's backend. Sets keep it from
+    /// creation, and rebuild it when deserialized.
+    pub fn disable_tag_index(&mut self) {
+        self.index = TagIndex::new();
+    }
+
+    /// Rebuild the index of members by tag, after [Set::disable_tag_index].
+    pub fn enable_tag_index(&mut self) {
+        self.index = TagIndex::of(self.backend(), self.map.iter().map(|(k, v)| (k, v.tag)));
+    }
+
+    /// An iterator visiting all registers with a tag greater than `tag`, in tag order, using the tag
+    /// index; an O(n) scan in storage order if [Set::disable_tag_index] was called.
+    ///
+    /// Registers are found by their current tag, so a register merged from another replica after
+    /// `tag` was seen, but tagged at or before it, is missed. This only finds every change since
+    /// `tag` if registers are merged in tag order; otherwise use [Set::delta_for] or a [Digest].
+    pub fn registers_since(&self, tag: Tag) -> impl Iterator<Item = Register<T, Tag, CL>> + '_ {
+        let indexed = self.index.since(tag).map(|keys| {
+            keys.filter_map(move |k| {
+                self.map
+                    .get(k)
+                    .map(|v| Register::make(k.clone(), v.tag, v.length))
+            })
+        });
+        let scanned = match indexed {
+            Some(_) => None,
+            None => Some(self.register_iter().filter(move |r| r.tag > tag)),
+        };
+        indexed
+            .into_iter()
+            .flatten()
+            .chain(scanned.into_iter().flatten())
+    }
+
+    /// An iterator visiting the registers which would change `other` when merged into it, in
+    /// storage order.
+    ///
+    /// Those are the members `other` doesn't have, or has with a lower causal length or tag.
+    pub fn delta_for<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> impl Iterator<Item = Register<T, Tag, CL>> + 'a {
+        self.register_iter()
+            .filter(move |delta| match other.map.get(&delta.item) {
+                Some(e) => delta.length > e.length || delta.tag > e.tag,
+                None => true,
+            })
+    }
+
     /// Filter out old remove tombstone deltas from the set.
     ///
     /// Remove deltas with a tag value less than `min_tag` will be removed.
     pub fn retain(&mut self, min_tag: Tag) {
+        let index = &mut self.index;
+        self.map.retain(|k, SubRegister { tag, length }| {
+            let keep = length.is_odd() || min_tag <= *tag;
+            if !keep {
+                index.update(k, Some(*tag), None);
+            }
+            keep
+        });
+    }
+}
+
+impl<T, Tag, CL, S> Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: BuildHasher + Clone,
+{
+    /// Create an empty `Set` which will use the given hasher to hash members.
+    pub fn with_hasher(hasher: S) -> Set<T, Tag, CL, S> {
+        Set {
+            map: HashMap::with_hasher(hasher.clone()),
+            index: TagIndex::of(hasher, std::iter::empty()),
+        }
+    }
+
+    /// Create an empty `Set` with space for at least `capacity` members, which will use the given
+    /// hasher to hash members.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Set<T, Tag, CL, S> {
+        Set {
+            map: HashMap::with_capacity_and_hasher(capacity, hasher.clone()),
+            index: TagIndex::of(hasher, std::iter::empty()),
+        }
+    }
+
+    /// Returns a reference to the set's hasher.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+}
+
+impl<T, Tag, CL> Set<T, Tag, CL, Ordered>
+where
+    T: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// An iterator visiting the elements and tags within `range`, in order.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (&T, Tag)> + '_
+    where
+        R: RangeBounds<T>,
+    {
+        self.map
+            .range(range)
+            .filter(|(_k, v)| v.length.is_odd())
+            .map(|(k, v)| (k, v.tag))
+    }
+
+    /// An iterator visiting the registers within `range`, in order.
+    pub fn register_range<R>(&self, range: R) -> impl Iterator<Item = Register<T, Tag, CL>> + '_
+    where
+        R: RangeBounds<T>,
+    {
         self.map
-            .retain(|_k, SubRegister { tag, length }| length.is_odd() || min_tag < *tag);
+            .range(range)
+            .map(|(k, v)| Register::make(k.clone(), v.tag, v.length))
+    }
+
+    /// Returns the first element in the set, and its tag.
+    pub fn first(&self) -> Option<(&T, Tag)> {
+        Self::visible(&self.map).next()
+    }
+
+    /// Returns the last element in the set, and its tag.
+    pub fn last(&self) -> Option<(&T, Tag)> {
+        Self::visible(&self.map).next_back()
+    }
+
+    fn visible(
+        map: &BTreeMap<T, SubRegister<Tag, CL>>,
+    ) -> impl DoubleEndedIterator<Item = (&T, Tag)> + '_ {
+        map.iter()
+            .filter(|(_k, v)| v.length.is_odd())
+            .map(|(k, v)| (k, v.tag))
     }
 }
 
-impl<T, Tag, CL> DeltaCrdt for Set<T, Tag, CL>
+impl<T, Tag, CL, S> DeltaCrdt for Set<T, Tag, CL, S>
 where
     T: Key,
     Tag: TagT,
     CL: CausalLength,
+    S: Backend<T> + Default,
 {
     type Delta = Register<T, Tag, CL>;
+
+    fn bottom() -> Self {
+        Set::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.join_register(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+impl<T, Tag, CL, S> Coalesce for Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T> + Default,
+{
+    type DeltaKey = T;
+
+    fn delta_key(delta: &Self::Delta) -> T {
+        delta.item.clone()
+    }
+
+    fn coalesce(into: &mut Self::Delta, delta: &Self::Delta) {
+        into.tag = max(into.tag, delta.tag);
+        into.length = max(into.length, delta.length);
+    }
 }
 
 #[cfg(feature = "serialization")]
 mod serialization {
     use super::*;
-    use serde::de::{SeqAccess, Visitor};
+    use serde::de::{DeserializeSeed, SeqAccess, Visitor};
     use serde::ser::SerializeSeq;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use std::fmt::Formatter;
     use std::marker::PhantomData;
 
-    impl<T, Tag, CL> Serialize for Set<T, Tag, CL>
+    impl<T, Tag, CL, B> Serialize for Set<T, Tag, CL, B>
     where
         T: Key + Serialize,
         Tag: TagT + Serialize,
         CL: CausalLength + Serialize,
+        B: Backend<T>,
     {
         fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
         where
@@ -186,15 +477,36 @@ mod serialization {
         }
     }
 
-    struct DeltaVisitor<T, Tag, CL>(PhantomData<T>, PhantomData<Tag>, PhantomData<CL>);
+    /// Deserializes a [Set] whose members are stored using `backend`.
+    ///
+    /// The `Deserialize` impl creates the backend with `Default`. Use this seed instead to carry a
+    /// seeded hasher, or one without `Default`, through deserialization: hashers aren't
+    /// serialized along with the set.
+    pub struct SetSeed<T, Tag, CL, B> {
+        backend: B,
+        marker: PhantomData<(T, Tag, CL)>,
+    }
 
-    impl<'de, T, Tag, CL> Visitor<'de> for DeltaVisitor<T, Tag, CL>
+    impl<T, Tag, CL, B> SetSeed<T, Tag, CL, B> {
+        /// Create a seed which stores the deserialized members using `backend`.
+        pub fn new(backend: B) -> SetSeed<T, Tag, CL, B> {
+            SetSeed {
+                backend,
+                marker: PhantomData,
+            }
+        }
+    }
+
+    struct DeltaVisitor<T, Tag, CL, B>(B, PhantomData<(T, Tag, CL)>);
+
+    impl<'de, T, Tag, CL, B> Visitor<'de> for DeltaVisitor<T, Tag, CL, B>
     where
         T: Key + Deserialize<'de>,
         Tag: TagT + Deserialize<'de>,
         CL: CausalLength + Deserialize<'de>,
+        B: Backend<T>,
     {
-        type Value = HashMap<T, SubRegister<Tag, CL>>;
+        type Value = B::Store<SubRegister<Tag, CL>>;
 
         fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
             formatter.write_str("a tuple of key, value, tag, and causal length")
@@ -204,8 +516,7 @@ mod serialization {
         where
             A: SeqAccess<'de>,
         {
-            let mut map: HashMap<T, SubRegister<Tag, CL>> =
-                HashMap::with_capacity(seq.size_hint().unwrap_or(0));
+            let mut map = self.0.store_with_capacity(seq.size_hint().unwrap_or(0));
             while let Some(d) = seq.next_element::<(T, Tag, CL)>()? {
                 map.insert(
                     d.0,
@@ -219,27 +530,43 @@ mod serialization {
         }
     }
 
-    impl<'de, T, Tag, CL> Deserialize<'de> for Set<T, Tag, CL>
+    impl<'de, T, Tag, CL, B> DeserializeSeed<'de> for SetSeed<T, Tag, CL, B>
     where
-        T: Eq + Hash + Clone + Deserialize<'de>,
+        T: Key + Deserialize<'de>,
         Tag: TagT + Deserialize<'de>,
         CL: CausalLength + Deserialize<'de>,
+        B: Backend<T>,
     {
-        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        type Value = Set<T, Tag, CL, B>;
+
+        fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
         where
             D: Deserializer<'de>,
         {
-            let visitor = DeltaVisitor::<T, Tag, CL>(PhantomData, PhantomData, PhantomData);
-            let map = deserializer.deserialize_seq(visitor)?;
+            let map = deserializer.deserialize_seq(DeltaVisitor(self.backend, PhantomData))?;
+            let index = TagIndex::of(B::of(&map), map.iter().map(|(k, v)| (k, v.tag)));
+            Ok(Set { map, index })
+        }
+    }
 
-            Ok(Set { map })
+    impl<'de, T, Tag, CL, B> Deserialize<'de> for Set<T, Tag, CL, B>
+    where
+        T: Key + Deserialize<'de>,
+        Tag: TagT + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+        B: Backend<T> + Default,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            SetSeed::new(B::default()).deserialize(deserializer)
         }
     }
 }
 
 #[cfg(feature = "serialization")]
 pub use serialization::*;
-use std::collections::hash_map::Entry;
 
 #[cfg(test)]
 mod tests {
@@ -402,6 +729,188 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_deltas() {
+        let mut cls1: Set<&str, u32, u16> = Set::new();
+        let mut cls2: Set<&str, u32, u16> = Set::new();
+
+        let mut deltas = vec![
+            cls1.add("foo", 1),
+            cls1.add("bar", 1),
+            cls1.remove("foo", 2).unwrap(),
+            cls1.add("foo", 3),
+        ];
+        assert_eq!(cls1.remove("baz", 4), None);
+        deltas.push(cls1.remove("bar", 4).unwrap());
+        // already removed
+        assert_eq!(cls1.remove("bar", 5), None);
+        for delta in deltas {
+            cls2.merge_register(delta, 0);
+        }
+        assert_eq!(cls1, cls2);
+    }
+
+    #[test]
+    fn test_merge_report() {
+        let mut cls1: Set<&str, u32, u16> = Set::new();
+        let mut cls2: Set<&str, u32, u16> = Set::new();
+
+        cls1.add("foo", 1);
+        cls1.add("bar", 1);
+        cls2.merge(&cls1, 0);
+        cls1.remove("foo", 2);
+        cls1.add("baz", 2);
+        cls1.add("old", 0);
+        cls1.remove("old", 0);
+
+        let mut changes = cls2.merge_report(&cls1, 1);
+        changes.sort_by_key(|c| *c.key());
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added("baz", ()),
+                Change::Removed("foo", ()),
+                Change::Ignored("old"),
+            ]
+        );
+        assert_eq!(
+            cls2.merge_register_report(cls1.add("bar", 3), 0),
+            Change::Unchanged("bar")
+        );
+    }
+
+    #[test]
+    fn test_registers_since() {
+        let mut cls: Set<&str, u32, u16> = Set::new();
+        cls.add("foo", 1);
+        cls.add("bar", 2);
+        cls.add("baz", 3);
+        cls.remove("foo", 4);
+        cls.merge_register(Register::make("qux", 0, 1), 0);
+        let since: Vec<&str> = cls.registers_since(1).map(|r| r.item).collect();
+        assert_eq!(since, vec!["bar", "baz", "foo"]);
+
+        cls.remove("bar", 5);
+        cls.retain(5);
+        let since: Vec<&str> = cls.registers_since(0).map(|r| r.item).collect();
+        assert_eq!(since, vec!["baz", "bar"]);
+        assert_eq!(cls.registers_since(5).count(), 0);
+
+        // a register merged later, but tagged before the last sync, is missed
+        cls.merge_register(Register::make("old", 2, 1), 0);
+        assert_eq!(cls.registers_since(4).count(), 1);
+
+        // without the index, every register is scanned
+        let mut unindexed = cls.clone();
+        unindexed.disable_tag_index();
+        let mut since: Vec<&str> = unindexed.registers_since(2).map(|r| r.item).collect();
+        since.sort_unstable();
+        assert_eq!(since, vec!["bar", "baz"]);
+    }
+
+    #[test]
+    fn test_delta_for() {
+        let mut cls1: Set<&str, u32, u16> = Set::new();
+        cls1.add("foo", 1);
+        cls1.add("bar", 1);
+        let mut cls2 = cls1.clone();
+        assert_eq!(cls1.delta_for(&cls2).count(), 0);
+
+        cls1.remove("foo", 2);
+        cls1.add("baz", 2);
+        cls2.add("qux", 3);
+        let mut deltas: Vec<_> = cls1.delta_for(&cls2).map(|r| *r.item()).collect();
+        deltas.sort_unstable();
+        assert_eq!(deltas, vec!["baz", "foo"]);
+
+        for delta in cls1.delta_for(&cls2).collect::<Vec<_>>() {
+            cls2.merge_register(delta, 0);
+        }
+        assert_eq!(cls1.delta_for(&cls2).count(), 0);
+        assert_eq!(
+            cls2.delta_for(&cls1).map(|r| *r.item()).collect::<Vec<_>>(),
+            vec!["qux"]
+        );
+    }
+
+    #[test]
+    fn test_ordered() {
+        let mut cls: OrderedSet<u32, u32, u16> = OrderedSet::new();
+
+        for i in (0..10).rev() {
+            cls.add(i, 1);
+        }
+        cls.remove(0, 2);
+        cls.remove(5, 2);
+        cls.remove(9, 2);
+
+        let values: Vec<u32> = cls.iter().map(|(k, _)| *k).collect();
+        assert_eq!(values, vec![1, 2, 3, 4, 6, 7, 8]);
+        let values: Vec<u32> = cls.range(3..7).map(|(k, _)| *k).collect();
+        assert_eq!(values, vec![3, 4, 6]);
+        let registers: Vec<u32> = cls.register_range(..=1).map(|r| r.item).collect();
+        assert_eq!(registers, vec![0, 1]);
+        assert_eq!(cls.first(), Some((&1, 1)));
+        assert_eq!(cls.last(), Some((&8, 1)));
+
+        let registers: Vec<u32> = cls.register_iter().map(|r| r.item).collect();
+        assert_eq!(registers, (0..10).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_hasher() {
+        type Hasher = std::hash::BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+        let mut cls: Set<u32, u32, u16, Hasher> =
+            Set::with_capacity_and_hasher(8, Hasher::default());
+        cls.add(1, 1);
+        cls.add(2, 1);
+        let mut cls2 = Set::with_hasher(cls.hasher().clone());
+        cls2.merge(&cls, 0);
+        assert_eq!(cls, cls2);
+
+        #[cfg(feature = "serialization")]
+        {
+            let data = serde_json::to_vec(&cls).unwrap();
+            let cls3: Set<u32, u32, u16, Hasher> = serde_json::from_slice(&data).unwrap();
+            assert_eq!(cls, cls3);
+        }
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_deserialize_seed() {
+        use serde::de::DeserializeSeed;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        // a seeded hasher without a Default impl
+        #[derive(Clone, Debug)]
+        struct Seeded(u64);
+
+        impl BuildHasher for Seeded {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> DefaultHasher {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(self.0);
+                hasher
+            }
+        }
+
+        let mut cls: Set<u32, u32, u16, Seeded> = Set::with_hasher(Seeded(7));
+        cls.add(1, 1);
+        cls.add(2, 1);
+        cls.remove(2, 2);
+
+        let data = serde_json::to_string(&cls).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&data);
+        let cls2 = SetSeed::new(Seeded(7))
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(cls2.hasher().0, 7);
+        assert_eq!(cls, cls2);
+    }
+
     #[cfg(feature = "serialization")]
     #[test]
     fn test_serialization() {
@@ -419,6 +928,8 @@ mod tests {
         let data = serde_json::to_vec(&cls).unwrap();
         let cls2: Set<&str, u32, u16> = serde_json::from_slice(&data).unwrap();
         assert_eq!(cls.map, cls2.map);
+        // the tag index is rebuilt
+        assert_eq!(cls2.registers_since(time_2).count(), 1);
     }
 
     #[test]
@@ -470,6 +981,39 @@ mod tests {
         left == right
     }
 
+    #[quickcheck]
+    fn is_tag_index_consistent(
+        xs: Vec<Register<u8, u8, u8>>,
+        ys: Vec<Register<u8, u8, u8>>,
+        ops: Vec<Op>,
+        min_tag: u8,
+        since: u8,
+    ) -> bool {
+        let mut set = Set::default();
+        set.disable_tag_index();
+        set.enable_tag_index();
+        for (tag, op) in ops.into_iter().enumerate() {
+            match op {
+                Op::Insert(k) => {
+                    set.add(k, tag as u8);
+                }
+                Op::Delete(k) => {
+                    set.remove(k, tag as u8);
+                }
+                Op::Get(_) => {}
+            }
+        }
+        let mut set = xs.iter().fold(set, merge);
+        set.retain(min_tag);
+        let mut set = ys.iter().fold(set, merge);
+        set.retain(min_tag / 2);
+        let mut indexed: Vec<_> = set.registers_since(since).collect();
+        let mut scanned: Vec<_> = set.register_iter().filter(|r| r.tag > since).collect();
+        indexed.sort_by_key(|r| r.item);
+        scanned.sort_by_key(|r| r.item);
+        indexed == scanned
+    }
+
     use quickcheck::{Arbitrary, Gen};
     #[derive(Clone, Debug)]
     enum Op {
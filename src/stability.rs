@@ -0,0 +1,205 @@
+use super::*;
+use crate::storage::Backend;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+
+/// A collection which keeps remove tombstones until told to drop them.
+pub trait Retain<Tag> {
+    /// Filter out remove tombstones with a tag value less than `min_tag`.
+    fn retain(&mut self, min_tag: Tag);
+}
+
+impl<T, Tag, CL, S> Retain<Tag> for Set<T, Tag, CL, S>
+where
+    T: Key,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<T>,
+{
+    fn retain(&mut self, min_tag: Tag) {
+        self.retain(min_tag);
+    }
+}
+
+impl<K, V, Tag, CL, S> Retain<Tag> for Map<K, V, Tag, CL, S>
+where
+    K: Key + Ord,
+    V: Value + Hash + Eq + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+    S: Backend<K>,
+{
+    fn retain(&mut self, min_tag: Tag) {
+        self.retain(min_tag);
+    }
+}
+
+impl<K, C, Tag, CL> Retain<Tag> for NestedMap<K, C, Tag, CL>
+where
+    K: Key,
+    C: DeltaCrdt + Retain<Tag>,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn retain(&mut self, min_tag: Tag) {
+        self.retain(min_tag);
+    }
+}
+
+/// Causal stability tracker
+///
+/// Tracks the latest tag each known replica has acknowledged, meaning it has merged every register
+/// with that tag or older. The minimum over all replicas is the stable horizon: every replica has
+/// seen every remove at or before it, so tombstones older than it can be dropped without the removed
+/// items coming back. The horizon is also the `min_tag` to pass to `merge_register`, which ignores
+/// the same removes that retain drops.
+///
+/// Every replica must be added before it exchanges any state, or tombstones it hasn't seen may be
+/// dropped. Dropping them is also only safe if no replica creates a tag at or below one it has
+/// acknowledged, see [Stability::ack]. Tags stamped by a [Replica] always satisfy this; tags
+/// passed to [Set] and [Map] directly are up to the caller.
+#[derive(Clone, Debug, Default)]
+pub struct Stability<R, Tag>
+where
+    R: Key,
+    Tag: TagT,
+{
+    // `None` until the replica has acknowledged anything
+    acked: HashMap<R, Option<Tag>>,
+}
+
+impl<R, Tag> Stability<R, Tag>
+where
+    R: Key,
+    Tag: TagT,
+{
+    /// Create a new `Stability` tracking no replicas
+    pub fn new() -> Stability<R, Tag> {
+        Stability {
+            acked: HashMap::new(),
+        }
+    }
+
+    /// Start tracking a replica, which has acknowledged nothing yet.
+    ///
+    /// Does nothing if the replica is already known.
+    pub fn add_replica(&mut self, replica: R) {
+        self.acked.entry(replica).or_insert(None);
+    }
+
+    /// Stop tracking a replica, which may advance the horizon.
+    pub fn remove_replica(&mut self, replica: &R) {
+        self.acked.remove(replica);
+    }
+
+    /// Record that a replica has merged every register with a tag up to and including `tag`.
+    ///
+    /// The replica must never tag a later operation with `tag` or anything below it: a remove
+    /// tagged below the horizon is ignored by replicas using it as `min_tag`, and an add can bring
+    /// back a member whose tombstone was dropped.
+    ///
+    /// Acknowledgements from unknown replicas are ignored.
+    pub fn ack(&mut self, replica: &R, tag: Tag) {
+        if let Some(acked) = self.acked.get_mut(replica) {
+            *acked = Some(acked.map_or(tag, |acked| max(acked, tag)));
+        }
+    }
+
+    /// Returns the latest tag a replica has acknowledged.
+    pub fn acked(&self, replica: &R) -> Option<Tag> {
+        self.acked.get(replica).copied().flatten()
+    }
+
+    /// Returns the stable horizon, or `None` if any replica hasn't acknowledged anything yet, or
+    /// no replicas are known.
+    pub fn horizon(&self) -> Option<Tag> {
+        let mut acked = self.acked.values().copied();
+        let first = acked.next()??;
+        acked.try_fold(first, |horizon, acked| {
+            acked.map(|acked| min(horizon, acked))
+        })
+    }
+
+    /// Drop the tombstones of a collection which are older than the stable horizon.
+    ///
+    /// Returns false, leaving the collection as is, if there's no horizon yet.
+    pub fn retain<C>(&self, crdt: &mut C) -> bool
+    where
+        C: Retain<Tag>,
+    {
+        match self.horizon() {
+            Some(horizon) => {
+                crdt.retain(horizon);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_horizon() {
+        let mut stability: Stability<u8, u32> = Stability::new();
+        assert_eq!(stability.horizon(), None);
+        stability.add_replica(1);
+        stability.add_replica(2);
+        stability.ack(&1, 5);
+        assert_eq!(stability.horizon(), None);
+
+        stability.ack(&2, 3);
+        assert_eq!(stability.horizon(), Some(3));
+        // old acknowledgements don't move the horizon back
+        stability.ack(&2, 1);
+        stability.ack(&3, 0);
+        assert_eq!(stability.acked(&2), Some(3));
+        assert_eq!(stability.horizon(), Some(3));
+
+        stability.remove_replica(&2);
+        assert_eq!(stability.horizon(), Some(5));
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut stability: Stability<&str, u32> = Stability::new();
+        stability.add_replica("a");
+        stability.add_replica("b");
+
+        let mut set: Set<&str, u32, u16> = Set::new();
+        set.add("foo", 1);
+        set.add("bar", 2);
+        set.remove("foo", 3);
+        set.remove("bar", 4);
+        let mut map: Map<&str, u32, u32, u16> = Map::new();
+        map.insert("foo", 1, 1);
+        map.remove("foo", 3);
+
+        assert!(!stability.retain(&mut set));
+        assert_eq!(set.register_iter().count(), 2);
+
+        stability.ack(&"a", 5);
+        stability.ack(&"b", 4);
+        assert!(stability.retain(&mut set));
+        assert!(stability.retain(&mut map));
+        let tombstones: Vec<_> = set.register_iter().map(|r| *r.item()).collect();
+        assert_eq!(tombstones, vec!["bar"]);
+        assert_eq!(map.register_iter().count(), 0);
+
+        // a remove older than the horizon is ignored rather than resurrecting anything
+        let horizon = stability.horizon().unwrap();
+        assert_eq!(
+            set.merge_register_report(Register::make("foo", 3, 2), horizon),
+            Change::Ignored("foo")
+        );
+        // one tagged at the horizon is merged, so its tombstone was kept
+        assert_eq!(
+            set.merge_register_report(Register::make("bar", horizon, 2), horizon),
+            Change::Unchanged("bar")
+        );
+        assert!(stability.retain(&mut set));
+        assert_eq!(set.register_iter().count(), 1);
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::{btree_map, hash_map, BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+/// Storage backend for [Set](crate::Set) and [Map](crate::Map) entries.
+///
+/// The collections keep their entries in a store created by their backend type parameter. The
+/// CRDT logic only uses the [Storage] trait, so any key/value store can be plugged in by
+/// implementing [Storage] for it, and [Backend] for a type which creates it:
+///
+/// ```
+/// use causal_length::{Backend, Set};
+/// use std::collections::BTreeMap;
+///
+/// #[derive(Default)]
+/// struct Sorted;
+///
+/// impl<K: Ord> Backend<K> for Sorted {
+///     type Store<V> = BTreeMap<K, V>;
+///
+///     fn store<V>(&self) -> Self::Store<V> {
+///         BTreeMap::new()
+///     }
+///
+///     fn of<V>(_store: &Self::Store<V>) -> Self {
+///         Sorted
+///     }
+/// }
+///
+/// let mut s: Set<u32, u32, u16, Sorted> = Set::new();
+/// s.add(2, 0);
+/// s.add(1, 0);
+/// assert_eq!(s.iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![1, 2]);
+/// ```
+pub trait Backend<K> {
+    /// The store holding the entries of a collection
+    type Store<V>: Storage<K, V>;
+
+    /// Create an empty store
+    fn store<V>(&self) -> Self::Store<V>;
+
+    /// Create an empty store with room for at least `capacity` entries, if the store preallocates.
+    fn store_with_capacity<V>(&self, capacity: usize) -> Self::Store<V> {
+        let _ = capacity;
+        self.store()
+    }
+
+    /// Returns the backend which created `store`.
+    fn of<V>(store: &Self::Store<V>) -> Self;
+}
+
+/// Key/value store holding the entries of a collection.
+///
+/// The collections never remove entries individually: removed members are kept as tombstones until
+/// [retain](Storage::retain) drops them. [remove](Storage::remove) is only used by auxiliary
+/// indexes.
+pub trait Storage<K, V> {
+    /// Iterator over all entries
+    type Iter<'a>: Iterator<Item = (&'a K, &'a V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    /// Returns the number of entries, including tombstones.
+    fn len(&self) -> usize;
+
+    /// Returns true if the store holds no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the entry for `key`.
+    fn get(&self, key: &K) -> Option<&V>;
+
+    /// Returns a mutable reference to the entry for `key`.
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+
+    /// Insert or replace the entry for `key`.
+    fn insert(&mut self, key: K, value: V);
+
+    /// Insert `value` for `key`, or if there is already an entry, combine `value` into it with `f`.
+    ///
+    /// Returns a mutable reference to the entry.
+    fn upsert<F>(&mut self, key: K, value: V, f: F) -> &mut V
+    where
+        F: FnOnce(&mut V, V);
+
+    /// Remove the entry for `key`, returning it.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// Retains only the entries specified by the predicate.
+    fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool;
+
+    /// An iterator visiting all entries.
+    fn iter(&self) -> Self::Iter<'_>;
+}
+
+/// Backend which stores entries in a `HashMap` using the hasher `S`, visiting them in arbitrary
+/// order.
+impl<K, S> Backend<K> for S
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    type Store<V> = HashMap<K, V, S>;
+
+    fn store<V>(&self) -> Self::Store<V> {
+        HashMap::with_hasher(self.clone())
+    }
+
+    fn store_with_capacity<V>(&self, capacity: usize) -> Self::Store<V> {
+        HashMap::with_capacity_and_hasher(capacity, self.clone())
+    }
+
+    fn of<V>(store: &Self::Store<V>) -> Self {
+        store.hasher().clone()
+    }
+}
+
+impl<K, V, S> Storage<K, V> for HashMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Iter<'a>
+        = std::collections::hash_map::Iter<'a, K, V>
+    where
+        K: 'a,
+        V: 'a,
+        S: 'a;
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        HashMap::insert(self, key, value);
+    }
+
+    fn upsert<F>(&mut self, key: K, value: V, f: F) -> &mut V
+    where
+        F: FnOnce(&mut V, V),
+    {
+        match self.entry(key) {
+            hash_map::Entry::Occupied(entry) => {
+                let entry = entry.into_mut();
+                f(entry, value);
+                entry
+            }
+            hash_map::Entry::Vacant(entry) => entry.insert(value),
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        HashMap::remove(self, key)
+    }
+
+    fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        HashMap::retain(self, f)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMap::iter(self)
+    }
+}
+
+/// Backend which stores entries in a `BTreeMap`, visiting them in key order.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Ordered;
+
+impl<K> Backend<K> for Ordered
+where
+    K: Ord,
+{
+    type Store<V> = BTreeMap<K, V>;
+
+    fn store<V>(&self) -> Self::Store<V> {
+        BTreeMap::new()
+    }
+
+    fn of<V>(_store: &Self::Store<V>) -> Self {
+        Ordered
+    }
+}
+
+impl<K, V> Storage<K, V> for BTreeMap<K, V>
+where
+    K: Ord,
+{
+    type Iter<'a>
+        = std::collections::btree_map::Iter<'a, K, V>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        BTreeMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        BTreeMap::insert(self, key, value);
+    }
+
+    fn upsert<F>(&mut self, key: K, value: V, f: F) -> &mut V
+    where
+        F: FnOnce(&mut V, V),
+    {
+        match self.entry(key) {
+            btree_map::Entry::Occupied(entry) => {
+                let entry = entry.into_mut();
+                f(entry, value);
+                entry
+            }
+            btree_map::Entry::Vacant(entry) => entry.insert(value),
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        BTreeMap::retain(self, f)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        BTreeMap::iter(self)
+    }
+}
+
+// Keys of a collection's entries by tag, so entries changed since a tag can be found without
+// visiting all of them. Built with `TagIndex::of` by the collections' constructors; `TagIndex::new`
+// is the disabled index of a collection which opted out, as it costs an entry per key.
+// Not part of the collection's state: it isn't serialized, and is ignored when comparing.
+//
+// `B` is always `S::Store<()>`, spelled out by the collection so its derives are bounded on it.
+#[derive(Clone, Debug)]
+pub(crate) struct TagIndex<K, Tag, S, B> {
+    // `None` while disabled, otherwise creates the sets of keys, so they use the collection's hasher
+    backend: Option<S>,
+    tags: BTreeMap<Tag, B>,
+    key: PhantomData<K>,
+}
+
+impl<K, Tag, S, B> Default for TagIndex<K, Tag, S, B> {
+    fn default() -> Self {
+        TagIndex {
+            backend: None,
+            tags: BTreeMap::new(),
+            key: PhantomData,
+        }
+    }
+}
+
+impl<K, Tag, S, B> TagIndex<K, Tag, S, B>
+where
+    K: Clone,
+    Tag: Ord + Copy,
+    S: Backend<K, Store<()> = B>,
+    B: Storage<K, ()>,
+{
+    // A disabled index, which ignores updates.
+    pub(crate) fn new() -> TagIndex<K, Tag, S, B> {
+        TagIndex::default()
+    }
+
+    // An enabled index of `entries`.
+    pub(crate) fn of<'a, I>(backend: S, entries: I) -> TagIndex<K, Tag, S, B>
+    where
+        I: Iterator<Item = (&'a K, Tag)>,
+        K: 'a,
+    {
+        let mut index = TagIndex {
+            backend: Some(backend),
+            tags: BTreeMap::new(),
+            key: PhantomData,
+        };
+        for (key, tag) in entries {
+            index.update(key, None, Some(tag));
+        }
+        index
+    }
+
+    // Move `key` from tag `old` to tag `new`, where `None` means the entry doesn't exist.
+    pub(crate) fn update(&mut self, key: &K, old: Option<Tag>, new: Option<Tag>) {
+        let backend = match &self.backend {
+            Some(backend) => backend,
+            None => return,
+        };
+        if old == new {
+            return;
+        }
+        if let Some(old) = old {
+            if let Some(keys) = self.tags.get_mut(&old) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tags.remove(&old);
+                }
+            }
+        }
+        if let Some(new) = new {
+            self.tags
+                .entry(new)
+                .or_insert_with(|| backend.store::<()>())
+                .insert(key.clone(), ());
+        }
+    }
+
+    // Keys of the entries with a tag greater than `tag`, in tag order, or `None` if disabled.
+    pub(crate) fn since(&self, tag: Tag) -> Option<impl Iterator<Item = &K> + '_> {
+        self.backend.as_ref()?;
+        Some(
+            self.tags
+                .range((Bound::Excluded(tag), Bound::Unbounded))
+                .flat_map(|(_, keys)| keys.iter().map(|(key, _)| key)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Map, Set};
+
+    /// Insertion ordered store, standing in for an external store
+    #[derive(Clone, Debug, PartialEq)]
+    struct VecStore<K, V>(Vec<(K, V)>);
+
+    #[derive(Clone, Debug, Default)]
+    struct Insertion;
+
+    impl<K> Backend<K> for Insertion
+    where
+        K: Eq,
+    {
+        type Store<V> = VecStore<K, V>;
+
+        fn store<V>(&self) -> Self::Store<V> {
+            VecStore(Vec::new())
+        }
+
+        fn of<V>(_store: &Self::Store<V>) -> Self {
+            Insertion
+        }
+    }
+
+    impl<K, V> Storage<K, V> for VecStore<K, V>
+    where
+        K: Eq,
+    {
+        type Iter<'a>
+            = std::iter::Map<std::slice::Iter<'a, (K, V)>, fn(&'a (K, V)) -> (&'a K, &'a V)>
+        where
+            K: 'a,
+            V: 'a;
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn get(&self, key: &K) -> Option<&V> {
+            self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        }
+
+        fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.0.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+        }
+
+        fn insert(&mut self, key: K, value: V) {
+            match self.get_mut(&key) {
+                Some(v) => *v = value,
+                None => self.0.push((key, value)),
+            }
+        }
+
+        fn upsert<F>(&mut self, key: K, value: V, f: F) -> &mut V
+        where
+            F: FnOnce(&mut V, V),
+        {
+            let i = match self.0.iter().position(|(k, _)| *k == key) {
+                Some(i) => {
+                    f(&mut self.0[i].1, value);
+                    i
+                }
+                None => {
+                    self.0.push((key, value));
+                    self.0.len() - 1
+                }
+            };
+            &mut self.0[i].1
+        }
+
+        fn remove(&mut self, key: &K) -> Option<V> {
+            let i = self.0.iter().position(|(k, _)| k == key)?;
+            Some(self.0.remove(i).1)
+        }
+
+        fn retain<F>(&mut self, mut f: F)
+        where
+            F: FnMut(&K, &mut V) -> bool,
+        {
+            self.0.retain_mut(|(k, v)| f(k, v))
+        }
+
+        fn iter(&self) -> Self::Iter<'_> {
+            self.0.iter().map(|(k, v)| (k, v))
+        }
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let mut s: Set<&str, u32, u16, Insertion> = Set::new();
+        s.add("foo", 1);
+        s.add("bar", 1);
+        s.add("baz", 1);
+        s.remove("bar", 2);
+        let members: Vec<&str> = s.iter().map(|(k, _)| *k).collect();
+        assert_eq!(members, vec!["foo", "baz"]);
+        s.retain(3);
+        assert_eq!(s.register_iter().count(), 2);
+
+        let mut m: Map<&str, u32, u32, u16, Insertion> = Map::new();
+        m.insert("foo", 1, 1);
+        m.insert("bar", 2, 1);
+        let mut m2: Map<&str, u32, u32, u16, Insertion> = Map::new();
+        m2.merge(&m, 0);
+        m2.insert("foo", 3, 2);
+        m.merge(&m2, 0);
+        let entries: Vec<(&str, u32, u32)> = m.iter().collect();
+        assert_eq!(entries, vec![("foo", 3, 2), ("bar", 2, 1)]);
+    }
+}
@@ -0,0 +1,614 @@
+use super::*;
+use crate::list::Position;
+use crate::register::Register;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::Range;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// A run of characters at consecutive [Position]s, starting at `position`
+///
+/// The characters of a run inserted together share a prefix, the position allocated for the
+/// insert, so runs inserted concurrently at the same place never interleave.
+///
+/// [Text] deltas are [Register]s over spans.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Span<R, Tag> {
+    position: Position<R, Tag>,
+    text: String,
+}
+
+impl<R, Tag> Span<R, Tag> {
+    /// Accessor for the position of the first character
+    pub fn position(&self) -> &Position<R, Tag> {
+        &self.position
+    }
+
+    /// Accessor for the characters
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Segment<Tag, CL> {
+    text: String,
+    tag: Tag,
+    length: CL,
+}
+
+fn count(s: &str) -> u32 {
+    s.chars().count() as u32
+}
+
+// Characters `a..b` of `s`
+fn slice(s: &str, a: u32, b: u32) -> &str {
+    let mut indices = s.char_indices().map(|(i, _)| i).chain(Some(s.len()));
+    let start = indices.nth(a as usize).unwrap_or(s.len());
+    let end = indices.nth((b - a) as usize - 1).unwrap_or(s.len());
+    &s[start..end]
+}
+
+/// Causal Length Text
+///
+/// Plain text for collaborative editing, built like [List] with one dense [Position] per
+/// character, and causal length presence for each character. Inserts take a replica id the same
+/// way as the tag, so runs inserted concurrently at the same place with equal tags are both kept.
+/// Characters inserted or deleted
+/// together are stored as a single run, which is split only where later edits need it, so a large
+/// pasted document costs a handful of registers.
+///
+/// Positions and lengths count characters (`char`s), not bytes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Text<R, Tag, CL>
+where
+    R: TagT,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    // Runs keyed by the position of their first character. No run contains a position between
+    // the start and end of another run.
+    segments: BTreeMap<Position<R, Tag>, Segment<Tag, CL>>,
+}
+
+impl<R, Tag, CL> Text<R, Tag, CL>
+where
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    /// Create a new empty `Text`
+    pub fn new() -> Text<R, Tag, CL> {
+        Text {
+            segments: BTreeMap::new(),
+        }
+    }
+
+    fn visible(&self) -> impl Iterator<Item = (&Position<R, Tag>, &Segment<Tag, CL>)> + '_ {
+        self.segments.iter().filter(|(_, seg)| seg.length.is_odd())
+    }
+
+    /// Returns the number of visible characters.
+    pub fn len(&self) -> usize {
+        self.visible()
+            .map(|(_, seg)| seg.text.chars().count())
+            .sum()
+    }
+
+    /// Returns true if there are no visible characters.
+    pub fn is_empty(&self) -> bool {
+        self.visible().next().is_none()
+    }
+
+    // Start of the run holding visible character `index`, and the offset of the character in it.
+    fn locate(&self, mut index: usize) -> Option<(&Position<R, Tag>, u32)> {
+        for (start, seg) in self.visible() {
+            let n = seg.text.chars().count();
+            if index < n {
+                return Some((start, index as u32));
+            }
+            index -= n;
+        }
+        None
+    }
+
+    // Position of the next character after character `j` of the run at `start`, visible or not.
+    fn successor(&self, start: &Position<R, Tag>, j: u32) -> Option<Position<R, Tag>> {
+        if j + 1 < count(&self.segments[start].text) {
+            return Some(start.offset(j + 1));
+        }
+        self.segments
+            .range((Excluded(start), Unbounded))
+            .next()
+            .map(|(p, _)| p.clone())
+    }
+
+    /// Insert `text` before visible character `index` on behalf of `replica`.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `text` is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(
+        &mut self,
+        index: usize,
+        text: &str,
+        replica: R,
+        tag: Tag,
+    ) -> Option<Register<Span<R, Tag>, Tag, CL>> {
+        if text.is_empty() {
+            return None;
+        }
+        let (lo, hi) = if index == 0 {
+            (None, self.segments.keys().next().cloned())
+        } else {
+            let located = self.locate(index - 1);
+            assert!(
+                located.is_some(),
+                "insertion index (is {}) should be <= len",
+                index
+            );
+            let (start, j) = located.unwrap();
+            (Some(start.offset(j)), self.successor(&start.clone(), j))
+        };
+        let position = Position::between(lo.as_ref(), hi.as_ref(), replica, tag).child(1);
+        self.merge_span(position.clone(), text, tag, CL::one());
+        Some(Register::make(
+            Span {
+                position,
+                text: text.to_string(),
+            },
+            tag,
+            CL::one(),
+        ))
+    }
+
+    /// Delete the visible characters in `range`.
+    ///
+    /// Returns the delta [Register]s that reproduce this change when merged into another replica,
+    /// one per run touched.
+    pub fn delete(
+        &mut self,
+        range: Range<usize>,
+        tag: Tag,
+    ) -> Vec<Register<Span<R, Tag>, Tag, CL>> {
+        let mut deltas: Vec<Register<Span<R, Tag>, Tag, CL>> = Vec::new();
+        let mut index = 0;
+        for (start, seg) in self.visible() {
+            if index >= range.end {
+                break;
+            }
+            let n = seg.text.chars().count();
+            let (a, b) = (max(range.start, index), min(range.end, index + n));
+            if a < b {
+                let (a, b) = ((a - index) as u32, (b - index) as u32);
+                deltas.push(Register::make(
+                    Span {
+                        position: start.offset(a),
+                        text: slice(&seg.text, a, b).to_string(),
+                    },
+                    max(seg.tag, tag),
+                    seg.length + CL::one(),
+                ));
+            }
+            index += n;
+        }
+        for delta in &deltas {
+            self.merge_span(
+                delta.item.position.clone(),
+                &delta.item.text,
+                delta.tag,
+                delta.length,
+            );
+        }
+        deltas
+    }
+
+    /// An iterator visiting all registers in position order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<Span<R, Tag>, Tag, CL>> + '_ {
+        self.segments.iter().map(|(position, seg)| {
+            Register::make(
+                Span {
+                    position: position.clone(),
+                    text: seg.text.clone(),
+                },
+                seg.tag,
+                seg.length,
+            )
+        })
+    }
+
+    /// Merge a delta [Register] into the text.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    pub fn merge_register(&mut self, delta: Register<Span<R, Tag>, Tag, CL>, min_tag: Tag) {
+        if delta.length.is_even() && delta.tag < min_tag {
+            // ignore excessively old remove records
+            return;
+        }
+        let Register { item, tag, length } = delta;
+        self.merge_span(item.position, &item.text, tag, length);
+    }
+
+    /// Merge two texts.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        for delta in other.register_iter() {
+            self.merge_register(delta, min_tag);
+        }
+    }
+
+    /// Filter out old remove tombstone deltas from the text.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.segments
+            .retain(|_, seg| seg.length.is_odd() || min_tag <= seg.tag);
+    }
+
+    // Split the run straddling `pos`, so that characters before and after it are in separate
+    // runs. With `after`, the character at `pos` stays with the characters before it.
+    fn cut(&mut self, pos: &Position<R, Tag>, after: bool) {
+        let (start, n) = match self.segments.range(..=pos).next_back() {
+            Some((start, seg)) => (start.clone(), count(&seg.text)),
+            None => return,
+        };
+        // first character which belongs after the cut
+        let (mut lo, mut hi) = (0, n);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let p = start.offset(mid);
+            if p > *pos || (!after && p == *pos) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        if 0 < lo && lo < n {
+            let seg = self.segments.get_mut(&start).unwrap();
+            let tail = slice(&seg.text, lo, n).to_string();
+            let head = slice(&seg.text, 0, lo).len();
+            seg.text.truncate(head);
+            let rest = Segment {
+                text: tail,
+                tag: seg.tag,
+                length: seg.length,
+            };
+            self.segments.insert(start.offset(lo), rest);
+        }
+    }
+
+    // Returns true if any character became visible or hidden, or changed.
+    fn merge_span(&mut self, position: Position<R, Tag>, text: &str, tag: Tag, length: CL) -> bool {
+        let n = count(text);
+        if n == 0 {
+            return false;
+        }
+        let mut visible = false;
+        let last = position.offset(n - 1);
+        self.cut(&position, false);
+        self.cut(&last, true);
+
+        // Runs now lie either entirely inside or outside the span. Inside runs are either part of
+        // the same run as the span, or were inserted between two of its characters.
+        let inside: Vec<(Position<R, Tag>, u32)> = self
+            .segments
+            .range(position.clone()..=last.clone())
+            .map(|(p, seg)| (p.clone(), count(&seg.text)))
+            .collect();
+        let mut i = 0;
+        for (start, m) in inside {
+            let k = match start.run_offset(&position) {
+                Some(k) => k,
+                None => (0..n).find(|j| position.offset(*j) > start).unwrap_or(n),
+            };
+            if i < k {
+                self.segments.insert(
+                    position.offset(i),
+                    Segment {
+                        text: slice(text, i, k).to_string(),
+                        tag,
+                        length,
+                    },
+                );
+                visible |= length.is_odd();
+            }
+            i = k;
+            if start.run_offset(&position).is_some() {
+                let piece = slice(text, k, k + m);
+                let seg = self.segments.get_mut(&start).unwrap();
+                let was_odd = seg.length.is_odd();
+                // positions collide only if a replica reuses a tag, pick one deterministically
+                if (length, tag, piece) > (seg.length, seg.tag, seg.text.as_str()) {
+                    visible |= length.is_odd() && seg.text != piece;
+                    seg.text = piece.to_string();
+                }
+                seg.length = max(seg.length, length);
+                visible |= was_odd != seg.length.is_odd();
+                seg.tag = max(seg.tag, tag);
+                i = k + m;
+            }
+        }
+        if i < n {
+            self.segments.insert(
+                position.offset(i),
+                Segment {
+                    text: slice(text, i, n).to_string(),
+                    tag,
+                    length,
+                },
+            );
+            visible |= length.is_odd();
+        }
+        self.coalesce(&position, &last);
+        visible
+    }
+
+    // Join neighbouring runs around `from..=to` which continue each other, with the same tag and
+    // causal length.
+    fn coalesce(&mut self, from: &Position<R, Tag>, to: &Position<R, Tag>) {
+        let before = self.segments.range(..from).next_back().map(|(p, _)| p);
+        let after = self
+            .segments
+            .range((Excluded(to), Unbounded))
+            .next()
+            .map(|(p, _)| p);
+        let lo = before.unwrap_or(from).clone();
+        let keys: Vec<Position<R, Tag>> = match after {
+            Some(hi) => self.segments.range(lo..=hi.clone()),
+            None => self.segments.range(lo..),
+        }
+        .map(|(p, _)| p.clone())
+        .collect();
+
+        let mut keys = keys.into_iter();
+        let mut current = match keys.next() {
+            Some(p) => p,
+            None => return,
+        };
+        for next in keys {
+            let seg = &self.segments[&current];
+            let following = &self.segments[&next];
+            if next == current.offset(count(&seg.text))
+                && seg.tag == following.tag
+                && seg.length == following.length
+            {
+                let following = self.segments.remove(&next).unwrap();
+                self.segments
+                    .get_mut(&current)
+                    .unwrap()
+                    .text
+                    .push_str(&following.text);
+            } else {
+                current = next;
+            }
+        }
+    }
+}
+
+impl<R, Tag, CL> fmt::Display for Text<R, Tag, CL>
+where
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (_, seg) in self.visible() {
+            f.write_str(&seg.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R, Tag, CL> DeltaCrdt for Text<R, Tag, CL>
+where
+    R: TagT + Hash,
+    Tag: TagT + Hash,
+    CL: CausalLength,
+{
+    type Delta = Register<Span<R, Tag>, Tag, CL>;
+
+    fn bottom() -> Self {
+        Text::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        let Register { item, tag, length } = delta;
+        self.merge_span(item.position, &item.text, tag, length)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(feature = "serialization")]
+mod serialization {
+    use super::*;
+    use serde::de::{SeqAccess, Visitor};
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt::Formatter;
+    use std::marker::PhantomData;
+
+    impl<R, Tag, CL> Serialize for Text<R, Tag, CL>
+    where
+        R: TagT + Hash + Serialize,
+        Tag: TagT + Hash + Serialize,
+        CL: CausalLength + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq = serializer.serialize_seq(Some(self.segments.len()))?;
+            for (position, seg) in self.segments.iter() {
+                seq.serialize_element(&(position, &seg.text, seg.tag, seg.length))?;
+            }
+            seq.end()
+        }
+    }
+
+    struct DeltaVisitor<R, Tag, CL>(PhantomData<(R, Tag, CL)>);
+
+    impl<'de, R, Tag, CL> Visitor<'de> for DeltaVisitor<R, Tag, CL>
+    where
+        R: TagT + Hash + Deserialize<'de>,
+        Tag: TagT + Hash + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        type Value = Text<R, Tag, CL>;
+
+        fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+            formatter.write_str("a tuple of position, text, tag, and causal length")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut text = Text::new();
+            while let Some(d) = seq.next_element::<(Position<R, Tag>, String, Tag, CL)>()? {
+                text.merge_span(d.0, &d.1, d.2, d.3);
+            }
+            Ok(text)
+        }
+    }
+
+    impl<'de, R, Tag, CL> Deserialize<'de> for Text<R, Tag, CL>
+    where
+        R: TagT + Hash + Deserialize<'de>,
+        Tag: TagT + Hash + Deserialize<'de>,
+        CL: CausalLength + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_seq(DeltaVisitor(PhantomData))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use rand::RngExt;
+
+    #[test]
+    fn test_insert_delete() {
+        let mut text: Text<u8, u32, u16> = Text::new();
+        text.insert(0, "hello", 1, 1);
+        text.insert(5, " world", 1, 2);
+        text.insert(5, ",", 1, 3);
+        assert_eq!(text.to_string(), "hello, world");
+        assert_eq!(text.len(), 12);
+
+        let deltas = text.delete(3..8, 4);
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(text.to_string(), "helorld");
+        assert_eq!(text.insert(0, "", 1, 5), None);
+    }
+
+    #[test]
+    fn test_runs() {
+        let mut text: Text<u8, u32, u16> = Text::new();
+        let document = "x".repeat(100_000);
+        text.insert(0, &document, 1, 1);
+        assert_eq!(text.register_iter().count(), 1);
+
+        // splitting a run only costs the pieces
+        text.insert(50_000, "y", 1, 2);
+        text.delete(10..20, 3);
+        assert_eq!(text.register_iter().count(), 5);
+        assert_eq!(text.len(), 100_000 + 1 - 10);
+    }
+
+    #[test]
+    fn test_concurrent_edits() {
+        let mut t1: Text<u8, u32, u16> = Text::new();
+        let mut deltas = vec![t1.insert(0, "the quick fox", 1, 1).unwrap()];
+        let mut t2 = t1.clone();
+
+        deltas.extend(t1.insert(4, "very ", 1, 2));
+        deltas.extend(t1.delete(9..15, 3));
+        deltas.extend(t2.insert(10, "brown ", 2, 2));
+        deltas.extend(t2.delete(0..4, 3));
+        t1.merge(&t2, 0);
+        t2.merge(&t1, 0);
+        assert_eq!(t1, t2);
+        assert_eq!(t1.to_string(), "very brown fox");
+        replay(&t1, deltas);
+    }
+
+    #[test]
+    fn test_same_tag_inserts() {
+        let mut t1: Text<u8, u32, u16> = Text::new();
+        t1.insert(0, "ac", 1, 1);
+        let mut t2 = t1.clone();
+
+        // concurrent inserts at the same place with equal tags keep both runs
+        let d1 = t1.insert(1, "bb", 1, 2).unwrap();
+        let d2 = t2.insert(1, "BB", 2, 2).unwrap();
+        t1.merge_register(d2, 0);
+        t2.merge_register(d1, 0);
+        assert_eq!(t1, t2);
+        assert_eq!(t1.to_string(), "abbBBc");
+    }
+
+    #[test]
+    fn test_random_edits() {
+        let mut rng = rand::rng();
+        let mut replicas: Vec<Text<u8, u32, u16>> = vec![Text::new(), Text::new()];
+        let mut deltas = Vec::new();
+        for tag in 1..300 {
+            if tag % 50 == 0 {
+                let snapshot = replicas[0].clone();
+                replicas[1].merge(&snapshot, 0);
+            }
+            let replica = rng.random_range(0..2);
+            let r = &mut replicas[replica as usize];
+            let len = r.len();
+            if len > 0 && rng.random_bool(0.3) {
+                let a = rng.random_range(0..len);
+                let b = rng.random_range(a..=len);
+                deltas.extend(r.delete(a..b, tag));
+            } else {
+                let s: String = (0..rng.random_range(1..4))
+                    .map(|_| rng.random_range('a'..='z'))
+                    .collect();
+                deltas.extend(r.insert(rng.random_range(0..=len), &s, replica, tag));
+            }
+        }
+        let (a, b) = replicas.split_at_mut(1);
+        a[0].merge(&b[0], 0);
+        b[0].merge(&a[0], 0);
+        assert_eq!(a[0].to_string(), b[0].to_string());
+        replay(&a[0], deltas);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut text: Text<u8, u32, u16> = Text::new();
+        text.insert(0, "hello world", 1, 1);
+        text.delete(2..4, 2);
+
+        let data = serde_json::to_string(&text).unwrap();
+        let text2: Text<u8, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(text, text2);
+    }
+}
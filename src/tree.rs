@@ -0,0 +1,386 @@
+use super::*;
+use crate::map::Map;
+use crate::register::Register;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+#[cfg(feature = "serialization")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Causal Length Tree
+///
+/// A tree of nodes, each holding its parent in a causal length [Map] entry, where `None` is the
+/// root. A move is a new parent for the node, merged with the same rules as [Map], so moves merge
+/// out of order.
+///
+/// Concurrent moves can form a cycle. Cycles are broken deterministically when the tree is read:
+/// of the nodes in the cycle, the one whose parent has the greatest tag (then greatest node) is
+/// placed at the root. Nodes below a removed node are hidden along with it.
+///
+/// The resolved tree is cached until the next change, so reads don't repeat the cycle breaking.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Tree<N, Tag, CL>
+where
+    N: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    parents: Map<N, Option<N>, Tag, CL>,
+    #[cfg_attr(feature = "serialization", serde(skip))]
+    resolved: OnceLock<HashMap<N, Option<N>>>,
+}
+
+impl<N, Tag, CL> PartialEq for Tree<N, Tag, CL>
+where
+    N: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.parents == other.parents
+    }
+}
+
+impl<N, Tag, CL> Eq for Tree<N, Tag, CL>
+where
+    N: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+}
+
+impl<N, Tag, CL> Tree<N, Tag, CL>
+where
+    N: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    /// Create a new empty `Tree`
+    pub fn new() -> Tree<N, Tag, CL> {
+        Tree {
+            parents: Map::new(),
+            resolved: OnceLock::new(),
+        }
+    }
+
+    // The resolved tree, cached until the parents change.
+    fn resolved(&self) -> &HashMap<N, Option<N>> {
+        self.resolved.get_or_init(|| self.resolve())
+    }
+
+    // Entry point for every change to the parents, dropping the cached tree.
+    fn parents_mut(&mut self) -> &mut Map<N, Option<N>, Tag, CL> {
+        self.resolved.take();
+        &mut self.parents
+    }
+
+    // The parent of every node reachable from the root, with cycles broken.
+    fn resolve(&self) -> HashMap<N, Option<N>> {
+        let entries: HashMap<N, (Option<N>, Tag)> = self
+            .parents
+            .iter()
+            .map(|(node, parent, tag)| (node, (parent, tag)))
+            .collect();
+        let mut parents: HashMap<N, Option<N>> = entries
+            .iter()
+            .map(|(node, (parent, _))| (node.clone(), parent.clone()))
+            .collect();
+
+        let mut done = HashSet::new();
+        for start in entries.keys() {
+            let mut chain: Vec<N> = Vec::new();
+            let mut current = Some(start.clone());
+            while let Some(node) = current {
+                if done.contains(&node) {
+                    break;
+                }
+                if let Some(i) = chain.iter().position(|n| *n == node) {
+                    let loser = chain[i..]
+                        .iter()
+                        .max_by_key(|n| (entries[*n].1, *n))
+                        .unwrap()
+                        .clone();
+                    parents.insert(loser, None);
+                    break;
+                }
+                current = parents.get(&node).cloned().flatten();
+                chain.push(node);
+            }
+            done.extend(chain);
+        }
+
+        // keep only nodes whose ancestors are all present
+        let mut attached: HashMap<N, bool> = HashMap::new();
+        for start in parents.keys() {
+            let mut chain = Vec::new();
+            let mut current = Some(start);
+            let reachable = loop {
+                match current {
+                    None => break true,
+                    Some(node) => {
+                        if let Some(known) = attached.get(node) {
+                            break *known;
+                        }
+                        match parents.get(node) {
+                            Some(parent) => {
+                                chain.push(node);
+                                current = parent.as_ref();
+                            }
+                            None => break false,
+                        }
+                    }
+                }
+            };
+            for node in chain {
+                attached.insert(node.clone(), reachable);
+            }
+        }
+        parents.retain(|node, _| attached[node]);
+        parents
+    }
+
+    /// Returns true if the node is present, and attached to the root.
+    pub fn contains(&self, node: &N) -> bool {
+        self.resolved().contains_key(node)
+    }
+
+    /// Returns the parent of a node, or `None` for nodes at the root and nodes not in the tree.
+    pub fn parent(&self, node: &N) -> Option<N> {
+        self.resolved().get(node).cloned().flatten()
+    }
+
+    /// Returns the children of a node, in order.
+    pub fn children(&self, node: &N) -> Vec<N> {
+        self.children_of(Some(node))
+    }
+
+    /// Returns the nodes at the root, in order.
+    pub fn roots(&self) -> Vec<N> {
+        self.children_of(None)
+    }
+
+    fn children_of(&self, node: Option<&N>) -> Vec<N> {
+        let mut children: Vec<N> = self
+            .resolved()
+            .iter()
+            .filter(|(_, parent)| parent.as_ref() == node)
+            .map(|(child, _)| child.clone())
+            .collect();
+        children.sort();
+        children
+    }
+
+    /// Returns the nodes from the root down to `node`, or `None` if the node isn't in the tree.
+    pub fn path(&self, node: &N) -> Option<Vec<N>> {
+        let parents = self.resolved();
+        let mut path = vec![node.clone()];
+        let mut current = parents.get(node)?;
+        while let Some(parent) = current {
+            path.push(parent.clone());
+            current = &parents[parent];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Add a node below `parent`, or at the root if `parent` is `None`.
+    ///
+    /// Adding a node which is already in the tree moves it, see [Tree::move_node].
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if this would put the node below itself. That includes nodes hidden by a removed
+    /// ancestor, which keep their place below it.
+    pub fn add(
+        &mut self,
+        node: N,
+        parent: Option<N>,
+        tag: Tag,
+    ) -> Option<Register<(N, Option<N>), Tag, CL>> {
+        if self.contains(&node) {
+            return self.move_node(node, parent, tag);
+        }
+        if let Some(parent) = &parent {
+            if self.is_stored_ancestor(&node, parent) {
+                return None;
+            }
+        }
+        Some(self.parents_mut().insert(node, parent, tag))
+    }
+
+    // Whether `node` is `from` or one of its ancestors by the stored parents, which unlike the
+    // resolved tree also link the nodes below a removed one.
+    fn is_stored_ancestor(&self, node: &N, from: &N) -> bool {
+        let mut seen = HashSet::new();
+        let mut current = Some(from.clone());
+        while let Some(n) = current {
+            if n == *node {
+                return true;
+            }
+            if !seen.insert(n.clone()) {
+                return false;
+            }
+            current = self.parents.get(&n).and_then(|(parent, _)| parent.clone());
+        }
+        false
+    }
+
+    /// Move a node below `parent`, or to the root if `parent` is `None`.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if either node isn't in the tree, or the move would put `node` below itself.
+    pub fn move_node(
+        &mut self,
+        node: N,
+        parent: Option<N>,
+        tag: Tag,
+    ) -> Option<Register<(N, Option<N>), Tag, CL>> {
+        if !self.contains(&node) {
+            return None;
+        }
+        if let Some(parent) = &parent {
+            if self.path(parent)?.contains(&node) {
+                return None;
+            }
+        }
+        Some(self.parents_mut().insert(node, parent, tag))
+    }
+
+    /// Remove a node, hiding the nodes below it.
+    ///
+    /// Returns the delta [Register] that reproduces this change when merged into another replica,
+    /// or `None` if `node` isn't in the tree.
+    pub fn remove(&mut self, node: N, tag: Tag) -> Option<Register<(N, Option<N>), Tag, CL>> {
+        self.parents_mut().remove(node, tag)
+    }
+
+    /// An iterator visiting all registers in arbitrary order.
+    pub fn register_iter(&self) -> impl Iterator<Item = Register<(N, Option<N>), Tag, CL>> + '_ {
+        self.parents.register_iter()
+    }
+
+    /// Merge a delta [Register] into the tree.
+    ///
+    /// Remove registers with a tag value less than `min_tag` will be ignored.
+    pub fn merge_register(&mut self, delta: Register<(N, Option<N>), Tag, CL>, min_tag: Tag) {
+        self.parents_mut().merge_register(delta, min_tag);
+    }
+
+    /// Merge a delta [Register] into the tree, see [Tree::merge_register].
+    ///
+    /// Returns the effect the delta had on the node's parent entry, before cycles are broken.
+    pub fn merge_register_report(
+        &mut self,
+        delta: Register<(N, Option<N>), Tag, CL>,
+        min_tag: Tag,
+    ) -> Change<N, Option<N>> {
+        self.parents_mut().merge_register_report(delta, min_tag)
+    }
+
+    /// Merge two trees.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be ignored.
+    pub fn merge(&mut self, other: &Self, min_tag: Tag) {
+        self.parents_mut().merge(&other.parents, min_tag);
+    }
+
+    /// Filter out old remove tombstone deltas from the tree.
+    ///
+    /// Remove deltas with a tag value less than `min_tag` will be removed.
+    pub fn retain(&mut self, min_tag: Tag) {
+        self.parents_mut().retain(min_tag);
+    }
+}
+
+impl<N, Tag, CL> DeltaCrdt for Tree<N, Tag, CL>
+where
+    N: Key + Ord,
+    Tag: TagT,
+    CL: CausalLength,
+{
+    type Delta = Register<(N, Option<N>), Tag, CL>;
+
+    fn bottom() -> Self {
+        Tree::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.merge(other, Tag::default());
+    }
+
+    fn apply_delta(&mut self, delta: Self::Delta) -> bool {
+        self.parents_mut().apply_delta(delta)
+    }
+
+    fn deltas(&self) -> impl Iterator<Item = Self::Delta> + '_ {
+        self.register_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+
+    #[test]
+    fn test_tree() {
+        let mut t: Tree<&str, u32, u16> = Tree::new();
+        t.add("home", None, 1).unwrap();
+        t.add("user", Some("home"), 1).unwrap();
+        t.add("docs", Some("user"), 1).unwrap();
+        t.add("tmp", None, 1).unwrap();
+        assert_eq!(t.roots(), vec!["home", "tmp"]);
+        assert_eq!(t.path(&"docs"), Some(vec!["home", "user", "docs"]));
+
+        t.move_node("docs", Some("tmp"), 2).unwrap();
+        assert_eq!(t.children(&"tmp"), vec!["docs"]);
+        assert_eq!(t.parent(&"docs"), Some("tmp"));
+        // can't move a node below itself
+        assert_eq!(t.move_node("home", Some("user"), 3), None);
+        // nor by adding it again
+        assert_eq!(t.add("home", Some("user"), 3), None);
+        assert_eq!(t.parent(&"user"), Some("home"));
+
+        t.remove("home", 3).unwrap();
+        assert!(!t.contains(&"user"));
+        assert_eq!(t.path(&"user"), None);
+        assert_eq!(t.roots(), vec!["tmp"]);
+
+        // a hidden node can't be added below the nodes hidden with it
+        t.add("photos", Some("user"), 4).unwrap();
+        assert_eq!(t.add("user", Some("photos"), 5), None);
+        assert_eq!(t.parents.get(&"user"), Some((&Some("home"), 1)));
+    }
+
+    #[test]
+    fn test_concurrent_moves() {
+        let mut t1: Tree<&str, u32, u16> = Tree::new();
+        let mut deltas = vec![t1.add("a", None, 1).unwrap(), t1.add("b", None, 1).unwrap()];
+        let mut t2 = t1.clone();
+
+        // each move is fine locally, together they form a cycle
+        deltas.extend(t1.move_node("a", Some("b"), 2));
+        deltas.extend(t2.move_node("b", Some("a"), 3));
+        t1.merge(&t2, 0);
+        t2.merge(&t1, 0);
+        assert_eq!(t1, t2);
+        // the later move loses
+        assert_eq!(t1.roots(), vec!["b"]);
+        assert_eq!(t1.children(&"b"), vec!["a"]);
+        replay(&t1, deltas);
+    }
+
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut t: Tree<u8, u32, u16> = Tree::new();
+        t.add(1, None, 1);
+        t.add(2, Some(1), 1);
+        assert_eq!(t.children(&1), vec![2]);
+
+        let data = serde_json::to_string(&t).unwrap();
+        let t2: Tree<u8, u32, u16> = serde_json::from_str(&data).unwrap();
+        assert_eq!(t, t2);
+        assert_eq!(t2.children(&1), vec![2]);
+    }
+}